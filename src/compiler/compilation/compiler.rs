@@ -1,15 +1,123 @@
 use std::{
 	collections::HashMap,
 	io::{Error, Write},
+	str::FromStr,
 };
 
-use super::parser::{get_size, Instruction, Parsing, Token};
+use super::parser::{get_size, split_comment, Instruction, ParsedInstruction, Parsing, Token};
+
+/// Byte order of the words in a binary.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Endian {
+	#[default]
+	Little,
+	Big,
+}
+
+impl Endian {
+	pub fn from_bytes(self, bytes: [u8; 2]) -> u16 {
+		match self {
+			Endian::Little => u16::from_le_bytes(bytes),
+			Endian::Big => u16::from_be_bytes(bytes),
+		}
+	}
+}
+
+impl FromStr for Endian {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"little" => Ok(Endian::Little),
+			"big" => Ok(Endian::Big),
+			_ => Err(format!("Unknown endianness \"{}\"!", s)),
+		}
+	}
+}
+
+/// Compiles like [`compile`], but with the words in the given byte order.
+pub fn compile_with_endian<O: Write>(
+	parsing: &Parsing,
+	output: &mut O,
+	endian: Endian,
+) -> Result<(), String> {
+	match endian {
+		Endian::Little => compile(parsing, output),
+		Endian::Big => {
+			let mut binary = Vec::new();
+			compile(parsing, &mut binary)?;
+			for word in binary.chunks_exact_mut(2) {
+				word.swap(0, 1);
+			}
+			output.write_all(&binary).map_err(could_not_write)?;
+			output.flush().map_err(could_not_write)
+		}
+	}
+}
 
 pub fn compile<O: Write>(parsing: &Parsing, output: &mut O) -> Result<(), String> {
+	compile_each(parsing, |_, _, bytes| {
+		output.write_all(bytes).map_err(could_not_write)
+	})?;
+	output.flush().map_err(could_not_write)
+}
+
+/// Compiles like [`compile`], and also writes a listing with the address,
+/// bytes and size in words of every source line next to its text. Comments on
+/// instructions are lined up in a column after the code.
+pub fn compile_with_listing<O: Write, L: Write>(
+	parsing: &Parsing,
+	output: &mut O,
+	listing: &mut L,
+) -> Result<(), String> {
+	let mut emitted: HashMap<usize, (u16, Vec<u8>, Option<&str>)> = HashMap::new();
+	compile_each(parsing, |pointer, parsed_instruction, bytes| {
+		emitted
+			.entry(parsed_instruction.line_number)
+			.or_insert_with(|| (pointer, Vec::new(), parsed_instruction.comment.as_deref()))
+			.1
+			.extend(bytes);
+		output.write_all(bytes).map_err(could_not_write)
+	})?;
+	output.flush().map_err(could_not_write)?;
+
+	for (i, source) in parsing.lines.iter().enumerate() {
+		let line = match emitted.get(&(i + 1)) {
+			Some((address, bytes, comment)) => {
+				let hex = bytes
+					.iter()
+					.map(|b| format!("{:02X}", b))
+					.collect::<Vec<_>>()
+					.join(" ");
+				let size = format!("[{}]", bytes.len() / 2);
+				let code = split_comment(source).0;
+				match comment {
+					Some(c) => format!(
+						"{:04X}  {:23}  {:3}  {:23} # {}",
+						address, hex, size, code, c
+					),
+					None => format!("{:04X}  {:23}  {:3}  {}", address, hex, size, code),
+				}
+			}
+			None => format!("{:34}  {}", "", source),
+		};
+		writeln!(listing, "{}", line.trim_end()).map_err(could_not_write_listing)?;
+	}
+	listing.flush().map_err(could_not_write_listing)
+}
+
+/// Compiles every instruction in address order, handing each one's bytes to
+/// `f`.
+fn compile_each<'a, F>(parsing: &'a Parsing, mut f: F) -> Result<(), String>
+where
+	F: FnMut(u16, &'a ParsedInstruction, &[u8]) -> Result<(), String>,
+{
 	let mut pointer = 0;
+	let mut bytes = Vec::new();
 
 	while let Some(parser_instruction) = parsing.instructions.get(&pointer) {
-		compile_instruction(&parser_instruction.instruction, &parsing.labels, output).map_err(
+		bytes.clear();
+		compile_instruction(&parser_instruction.instruction, &parsing.labels, &mut bytes).map_err(
 			|e| {
 				format!(
 					"Error when compiling line {}.\n\t{}",
@@ -17,10 +125,11 @@ pub fn compile<O: Write>(parsing: &Parsing, output: &mut O) -> Result<(), String
 				)
 			},
 		)?;
+		f(pointer, parser_instruction, &bytes)?;
 		pointer += get_size(&parser_instruction.instruction);
 	}
 
-	output.flush().map_err(could_not_write)
+	Ok(())
 }
 
 fn compile_instruction<O: Write>(
@@ -51,7 +160,8 @@ fn compile_instruction<O: Write>(
 		Instruction::Out(a1) => out(a1, output),
 		Instruction::In(a1) => in_op(a1, output),
 		Instruction::Noop() => noop(output),
-		Instruction::Data(a1) => data(a1, output),
+		Instruction::Sub(a1, a2, a3) => sub(a1, a2, a3, output),
+		Instruction::Data(values) => data(values, labels, output),
 	}
 }
 
@@ -460,15 +570,198 @@ fn noop<O: Write>(output: &mut O) -> Result<(), String> {
 	output.write_all(&[21, 0]).map_err(could_not_write)
 }
 
-fn data<O: Write>(value: &Token, output: &mut O) -> Result<(), String> {
-	if let Token::Value(t) = value {
-		let t_bytes = t.to_le_bytes();
-		output.write_all(&t_bytes).map_err(could_not_write)
+/// Writes `a - b` as `a + not(b) + 1`. When the destination is `a` it's
+/// written as `not(not(a) + b)` instead, padded with a `noop`, so that `a`
+/// isn't overwritten before it's read. When `b` is that same register too the
+/// result is always 0, which is set directly and padded to the same size.
+fn sub<O: Write>(
+	register: &Token,
+	value_a: &Token,
+	value_b: &Token,
+	output: &mut O,
+) -> Result<(), String> {
+	if register != value_a {
+		not(register, value_b, output)?;
+		add(register, register, value_a, output)?;
+		add(register, register, &Token::Value(1), output)
+	} else if register == value_b {
+		set(register, &Token::Value(0), output)?;
+		(0..8).try_for_each(|_| noop(output))
 	} else {
-		Err("Data must be a literal.".to_string())
+		not(register, value_a, output)?;
+		add(register, register, value_b, output)?;
+		not(register, register, output)?;
+		noop(output)
 	}
 }
 
+fn data<O: Write>(
+	values: &[Token],
+	labels: &HashMap<String, u16>,
+	output: &mut O,
+) -> Result<(), String> {
+	for value in values {
+		let t = match value {
+			Token::Value(t) => t,
+			Token::Label(l) => labels.get(l).ok_or(format!("Undefined label \"{}\"!", l))?,
+		};
+		let t_bytes = t.to_le_bytes();
+		output.write_all(&t_bytes).map_err(could_not_write)?;
+	}
+	Ok(())
+}
+
 fn could_not_write(e: Error) -> String {
 	format!("Could not write to the output binary. {}", e)
 }
+
+fn could_not_write_listing(e: Error) -> String {
+	format!("Could not write to the listing. {}", e)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+
+	use super::{super::parser::parse, *};
+	use crate::{
+		compiler::disasm::decode,
+		runtime::{data::Data, vm::VM},
+	};
+
+	fn assemble(source: &str) -> Vec<u8> {
+		let mut binary = Vec::new();
+		compile(&parse(source.as_bytes()).unwrap(), &mut binary).unwrap();
+		binary
+	}
+
+	#[test]
+	fn sub_expansion() {
+		assert_eq!(
+			assemble("sub 32768 32769 32770\nhalt\n"),
+			assemble("not 32768 32770\nadd 32768 32768 32769\nadd 32768 32768 1\nhalt\n"),
+			"sub should expand to not and add."
+		);
+		assert_eq!(
+			assemble("sub 32768 32768 5\nhalt\n").len(),
+			assemble("sub 32768 32769 5\nhalt\n").len(),
+			"sub should have the same size whatever its registers."
+		);
+		assert_eq!(
+			assemble("sub 32768 32768 32768\nhalt\n").len(),
+			assemble("sub 32768 32769 5\nhalt\n").len(),
+			"sub of a register from itself into itself should have the same size."
+		);
+	}
+
+	#[test]
+	fn sub_results() {
+		let cases = [
+			("sub 32768 32769 32770", 0, 32766),
+			("sub 32768 32768 32770", 0, 32766),
+			("sub 32770 32769 32770", 2, 32766),
+			("sub 32768 32769 32769", 0, 0),
+			("sub 32768 32768 32768", 0, 0),
+		];
+		for &(source, register, expected) in cases.iter() {
+			let program = format!("set 32769 3\nset 32770 5\nset 32768 3\n{}\nhalt\n", source);
+			let memory = assemble(&program)
+				.chunks_exact(2)
+				.map(|c| u16::from_le_bytes([c[0], c[1]]))
+				.collect::<Vec<_>>();
+			let mut vm = VM::new(Data::new(&memory));
+			while !vm.step(&mut io::empty(), &mut io::sink()).unwrap().halted {}
+			assert_eq!(
+				vm.data.get_register(register),
+				Ok(expected),
+				"Running \"{}\".",
+				source
+			);
+		}
+	}
+
+	#[test]
+	fn data_list() {
+		assert_eq!(assemble("data 65 66 67\n"), vec![0x41, 0, 0x42, 0, 0x43, 0]);
+		assert_eq!(
+			assemble("data 65 66 67\nend: halt\njmp end\n"),
+			assemble("65\n66\n67\nhalt\njmp 3\n"),
+			"Each value should take up one address."
+		);
+		assert_eq!(
+			parse("data # Nothing\n".as_bytes()).map(|_| ()),
+			Err("line 1, column 1: data needs at least one value!".to_string())
+		);
+	}
+
+	#[test]
+	fn jump_table() {
+		let source = [
+			"jmp start",
+			"table: data first second third",
+			"start: halt",
+			"first: out 65",
+			"second: out 66",
+			"third: out 67",
+			"",
+		]
+		.join("\n");
+		let parsing = parse(source.as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		let memory = binary
+			.chunks_exact(2)
+			.map(|c| u16::from_le_bytes([c[0], c[1]]))
+			.collect::<Vec<_>>();
+		let table = parsing.label_address("table").unwrap() as usize;
+		let entries = ["first", "second", "third"]
+			.iter()
+			.map(|l| parsing.label_address(l).unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(memory[table..table + 3], entries[..]);
+		for &entry in &entries {
+			let decoded = decode(&memory, entry as usize).map(|(d, _)| d.to_string());
+			assert!(
+				decoded.as_deref().unwrap_or("").starts_with("out"),
+				"The entry {} should point to an out, not {:?}.",
+				entry,
+				decoded
+			);
+		}
+		assert_eq!(
+			parse("data nowhere\n".as_bytes()).map(|_| ()),
+			Err("Undefined label \"nowhere\" at line 1.".to_string())
+		);
+	}
+
+	#[test]
+	fn listing() {
+		let parsing = parse("# Print A\nstart: out 65 # A\n\thalt\n".as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		let mut listing = Vec::new();
+		compile_with_listing(&parsing, &mut binary, &mut listing).unwrap();
+		assert_eq!(binary, vec![19, 0, 65, 0, 0, 0]);
+		let expected = [
+			"                                    # Print A",
+			"0000  13 00 41 00              [2]  start: out 65           # A",
+			"0002  00 00                    [1]  \thalt",
+			"",
+		];
+		assert_eq!(String::from_utf8(listing), Ok(expected.join("\n")));
+	}
+
+	#[test]
+	fn listing_sizes() {
+		let source = ["halt", "add 32768 32769 1", "data 1 2 3"].join("\n");
+		let parsing = parse(source.as_bytes()).unwrap();
+		let mut listing = Vec::new();
+		compile_with_listing(&parsing, &mut io::sink(), &mut listing).unwrap();
+		let listing = String::from_utf8(listing).unwrap();
+		let sizes = listing.lines().map(|l| &l[31..34]).collect::<Vec<_>>();
+		assert_eq!(
+			sizes,
+			["[1]", "[4]", "[3]"],
+			"Data should count one word per value."
+		);
+	}
+}
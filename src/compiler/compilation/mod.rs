@@ -1,4 +1,6 @@
 mod compiler;
+mod optimizer;
 mod parser;
-pub use compiler::compile;
-pub use parser::{parse, Parsing};
+pub use compiler::{compile, compile_with_endian, compile_with_listing, Endian};
+pub use optimizer::{compile_optimized, remove_noop_clusters};
+pub use parser::{parse, Parsing, UndefinedLabelError, Warning};
@@ -0,0 +1,285 @@
+use std::{collections::HashSet, io::Write};
+
+use super::{
+	compiler::compile,
+	parser::{get_size, split_comment, Instruction, ParsedInstruction, Parsing, Token},
+};
+use crate::compiler::decompilation::Segment;
+
+/// Compiles like [`compile`], after removing instructions that do nothing:
+/// `noop`s that no label or jump goes to, except the padding of `.org`, and
+/// jumps to the instruction right after them. Labels and literal jump targets
+/// are moved along with the code, but other literal addresses, such as those
+/// read by `rmem`, are not.
+pub fn compile_optimized<O: Write>(parsing: &Parsing, output: &mut O) -> Result<(), String> {
+	compile(&optimize(parsing), output)
+}
+
+/// The target of a `jmp`, `jt`, `jf` or `call`, if it's an address.
+fn jump_target(instruction: &Instruction) -> Option<&Token> {
+	match instruction {
+		Instruction::Jmp(t)
+		| Instruction::Call(t)
+		| Instruction::Jt(_, t)
+		| Instruction::Jf(_, t) => Some(t),
+		_ => None,
+	}
+	.filter(|t| !matches!(t, Token::Value(v) if *v >= 32768))
+}
+
+/// Folds every run of at least `threshold` `noop`s in a row into one padding
+/// block. It compiles to the same bytes, but is listed on the line of the
+/// first `noop` with a comment saying how many it holds. Runs are split
+/// wherever a label or jump goes, so that those still land on an instruction.
+pub fn remove_noop_clusters(parsing: &mut Parsing, threshold: usize) {
+	let targets = targets(parsing);
+	let mut addresses = parsing.instructions.keys().copied().collect::<Vec<_>>();
+	addresses.sort_unstable();
+	// The start and length of every run of noops.
+	let mut runs: Vec<(u16, u16)> = Vec::new();
+	for addr in addresses {
+		if !matches!(parsing.instructions[&addr].instruction, Instruction::Noop()) {
+			continue;
+		}
+		match runs.last_mut() {
+			Some((start, length)) if *start + *length == addr && !targets.contains(&addr) => {
+				*length += 1
+			}
+			_ => runs.push((addr, 1)),
+		}
+	}
+	for (start, length) in runs {
+		if (length as usize) < threshold.max(2) {
+			continue;
+		}
+		for addr in start + 1..start + length {
+			parsing.instructions.remove(&addr);
+		}
+		let first = parsing.instructions.get_mut(&start).unwrap();
+		first.instruction = Instruction::Data(vec![Token::Value(21); length as usize]);
+		first.comment = Some(match first.comment.take() {
+			Some(comment) => format!("{} noops of padding, {}", length, comment),
+			None => format!("{} noops of padding", length),
+		});
+	}
+}
+
+/// Whether `parsed` comes from a `.org` line, such as its `noop` padding.
+fn from_org(parsing: &Parsing, parsed: &ParsedInstruction) -> bool {
+	parsing
+		.lines
+		.get(parsed.line_number - 1)
+		.is_some_and(|line| split_comment(line).0.trim_start().starts_with(".org"))
+}
+
+/// Every address that a label is at or a jump goes to.
+fn targets(parsing: &Parsing) -> HashSet<u16> {
+	let resolve = |token: &Token| match token {
+		Token::Value(v) => Some(*v),
+		Token::Label(name) => parsing.labels.get(name).copied(),
+	};
+	parsing
+		.labels
+		.values()
+		.copied()
+		.chain(
+			parsing
+				.instructions
+				.values()
+				.filter_map(|parsed| jump_target(&parsed.instruction).and_then(resolve)),
+		)
+		.collect()
+}
+
+fn optimize(parsing: &Parsing) -> Parsing {
+	let resolve = |token: &Token| match token {
+		Token::Value(v) => Some(*v),
+		Token::Label(name) => parsing.labels.get(name).copied(),
+	};
+	let targets = targets(parsing);
+
+	let mut addresses = parsing.instructions.keys().copied().collect::<Vec<_>>();
+	addresses.sort_unstable();
+	// The start and size of every removed instruction, in address order.
+	let mut removed = Vec::new();
+	for &addr in &addresses {
+		let parsed = &parsing.instructions[&addr];
+		let instruction = &parsed.instruction;
+		let size = get_size(instruction);
+		let useless = match instruction {
+			Instruction::Noop() => !targets.contains(&addr) && !from_org(parsing, parsed),
+			Instruction::Jmp(_) | Instruction::Jt(_, _) | Instruction::Jf(_, _) => {
+				jump_target(instruction).and_then(resolve) == Some(addr + size)
+			}
+			_ => false,
+		};
+		if useless {
+			removed.push((addr, size));
+		}
+	}
+	let relocate = |addr: u16| {
+		addr - removed
+			.iter()
+			.take_while(|&&(start, _)| start < addr)
+			.map(|&(_, size)| size)
+			.sum::<u16>()
+	};
+
+	let mut optimized = parsing.instructions.clone();
+	for (start, _) in &removed {
+		optimized.remove(start);
+	}
+	let instructions = optimized
+		.into_iter()
+		.map(|(addr, mut parsed)| {
+			match &mut parsed.instruction {
+				Instruction::Jmp(t)
+				| Instruction::Call(t)
+				| Instruction::Jt(_, t)
+				| Instruction::Jf(_, t) => {
+					if let Token::Value(v) = t {
+						if *v < 32768 {
+							*v = relocate(*v);
+						}
+					}
+				}
+				_ => {}
+			}
+			(relocate(addr), parsed)
+		})
+		.collect();
+	let labels = parsing
+		.labels
+		.iter()
+		.map(|(name, &addr)| (name.clone(), relocate(addr)))
+		.collect();
+	Parsing {
+		instructions,
+		labels,
+		warnings: parsing.warnings.clone(),
+		lines: parsing.lines.clone(),
+		sections: parsing
+			.sections
+			.iter()
+			.map(|section| Segment {
+				start: relocate(section.start as u16) as usize,
+				end: relocate(section.end as u16) as usize,
+				kind: section.kind,
+			})
+			.filter(|section| section.start < section.end)
+			.collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{compiler::compile_with_listing, parser::parse},
+		*,
+	};
+
+	fn compiled(source: &str, optimized: bool) -> Vec<u8> {
+		let parsing = parse(source.as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		if optimized {
+			compile_optimized(&parsing, &mut binary).unwrap();
+		} else {
+			compile(&parsing, &mut binary).unwrap();
+		}
+		binary
+	}
+
+	#[test]
+	fn remove_noop() {
+		assert_eq!(
+			compiled("out 65\nnoop\nloop: out 66\njmp loop\n", true),
+			compiled("out 65\nloop: out 66\njmp loop\n", false),
+			"The noop should be gone and loop should move back."
+		);
+		assert_eq!(
+			compiled("jmp 3\nhere: noop\n3: noop\njmp here\n", true),
+			compiled("jmp 3\nhere: noop\n3: noop\njmp here\n", false),
+			"Noops that are jumped to should stay."
+		);
+	}
+
+	#[test]
+	fn keep_org_padding() {
+		let source = "out 65\n.org 4 noop\nstart: out 66\njmp start\n";
+		assert_eq!(
+			compiled(source, true),
+			compiled(source, false),
+			"The padding should keep start at 4."
+		);
+	}
+
+	#[test]
+	fn move_sections() {
+		let source = [
+			"out 66",
+			".section data",
+			"message: data 72 105",
+			".section code",
+			"start: noop",
+			"noop",
+			"out 65",
+			"halt",
+		]
+		.join("\n");
+		let parsing = parse(source.as_bytes()).unwrap();
+		let sections = optimize(&parsing)
+			.sections()
+			.iter()
+			.map(|s| (s.start, s.end))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			sections,
+			vec![(0, 2), (2, 6), (6, 8)],
+			"The code section should shrink and the data move back."
+		);
+	}
+
+	#[test]
+	fn fold_noops() {
+		let source = [
+			"out 65",
+			"noop",
+			"noop",
+			"noop",
+			"jmp 8",
+			"noop # Kept apart by the jump.",
+			"noop",
+			"halt",
+			"",
+		]
+		.join("\n");
+		let mut parsing = parse(source.as_bytes()).unwrap();
+		let mut before = Vec::new();
+		compile(&parsing, &mut before).unwrap();
+		remove_noop_clusters(&mut parsing, 2);
+		let mut after = Vec::new();
+		let mut listing = Vec::new();
+		compile_with_listing(&parsing, &mut after, &mut listing).unwrap();
+		assert_eq!(after, before, "The bytes should be unchanged.");
+		let listing = String::from_utf8(listing).unwrap();
+		let lines = listing.lines().collect::<Vec<_>>();
+		assert_eq!(
+			lines[1],
+			"0002  15 00 15 00 15 00        [3]  noop                    # 3 noops of padding"
+		);
+		assert_eq!(lines[2], "                                    noop");
+		assert!(
+			lines[5].ends_with("[1]  noop                    # Kept apart by the jump."),
+			"The jump target should stay its own noop: {}",
+			lines[5]
+		);
+	}
+
+	#[test]
+	fn remove_jump_to_next() {
+		assert_eq!(
+			compiled("jt 32768 next\nnext: out 65\njmp 0\n", true),
+			compiled("out 65\njmp 0\n", false)
+		);
+	}
+}
@@ -1,6 +1,18 @@
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
+	convert::TryFrom,
+	fmt,
+	fs,
 	io::{BufRead, BufReader, Read},
+	iter,
+};
+
+use crate::{
+	compiler::{
+		decompilation::{Segment, SegmentKind},
+		symbols::read_symbols,
+	},
+	opcode,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -9,7 +21,7 @@ pub(super) enum Token {
 	Value(u16),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) enum Instruction {
 	Halt(),
 	Set(Token, Token),
@@ -33,19 +45,239 @@ pub(super) enum Instruction {
 	Out(Token),
 	In(Token),
 	Noop(),
-	Data(Token),
+	Sub(Token, Token, Token),
+	Data(Vec<Token>),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) struct ParsedInstruction {
 	pub line_number: usize,
 	pub instruction: Instruction,
+	pub comment: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Parsing {
 	pub(super) instructions: HashMap<u16, ParsedInstruction>,
 	pub(super) labels: HashMap<String, u16>,
+	pub(super) warnings: Vec<String>,
+	pub(super) lines: Vec<String>,
+	pub(super) sections: Vec<Segment>,
+}
+
+/// A reference to a label that's never defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UndefinedLabelError {
+	pub name: String,
+	pub line_number: usize,
+}
+
+impl fmt::Display for UndefinedLabelError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"Undefined label \"{}\" at line {}.",
+			self.name, self.line_number
+		)
+	}
+}
+
+/// Something suspicious in a program that still compiles.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+	UnreachableInstruction {
+		addr: u16,
+		mnemonic: &'static str,
+	},
+	MisalignedJump {
+		addr: u16,
+		mnemonic: &'static str,
+		target: u16,
+	},
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Warning::UnreachableInstruction {
+				addr,
+				mnemonic,
+			} => write!(
+				f,
+				"The {} at address {} follows a jmp or halt, but nothing jumps to it.",
+				mnemonic, addr
+			),
+			Warning::MisalignedJump {
+				addr,
+				mnemonic,
+				target,
+			} => write!(
+				f,
+				"The {} at address {} goes to {}, which isn't the start of an instruction.",
+				mnemonic, addr, target
+			),
+		}
+	}
+}
+
+impl Parsing {
+	/// Every label with its address, in no particular order.
+	pub fn labels(&self) -> impl Iterator<Item = (&str, u16)> {
+		self.labels
+			.iter()
+			.map(|(name, &addr)| (name.as_str(), addr))
+	}
+
+	pub fn label_address(&self, name: &str) -> Option<u16> {
+		self.labels.get(name).copied()
+	}
+
+	/// Checks that every label used by an instruction is defined, returning
+	/// every undefined use in line order.
+	pub fn validate_labels(&self) -> Result<(), Vec<UndefinedLabelError>> {
+		let mut errors = self
+			.instructions
+			.values()
+			.flat_map(|parsed| {
+				parsed
+					.instruction
+					.tokens()
+					.into_iter()
+					.filter_map(move |t| match t {
+						Token::Label(name) if !self.labels.contains_key(name) => {
+							Some(UndefinedLabelError {
+								name: name.clone(),
+								line_number: parsed.line_number,
+							})
+						}
+						_ => None,
+					})
+			})
+			.collect::<Vec<_>>();
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			errors.sort_by_key(|e| e.line_number);
+			Err(errors)
+		}
+	}
+
+	/// Finds instructions right after a `jmp` or `halt` that have no label
+	/// and aren't the literal target of any jump or call.
+	pub fn lint(&self) -> Vec<Warning> {
+		let mut targets = self.labels.values().cloned().collect::<HashSet<_>>();
+		for parsed in self.instructions.values() {
+			match &parsed.instruction {
+				Instruction::Jmp(Token::Value(t))
+				| Instruction::Call(Token::Value(t))
+				| Instruction::Jt(_, Token::Value(t))
+				| Instruction::Jf(_, Token::Value(t)) => {
+					targets.insert(*t);
+				}
+				_ => {}
+			}
+		}
+
+		let mut addresses = self.instructions.keys().cloned().collect::<Vec<_>>();
+		addresses.sort_unstable();
+		let mut warnings = Vec::new();
+		for pair in addresses.windows(2) {
+			let (previous, addr) = (&self.instructions[&pair[0]], pair[1]);
+			let ends_flow = matches!(
+				previous.instruction,
+				Instruction::Jmp(_) | Instruction::Halt()
+			);
+			if !ends_flow || targets.contains(&addr) {
+				continue;
+			}
+			if let Some(mnemonic) = self.instructions[&addr].instruction.mnemonic() {
+				warnings.push(Warning::UnreachableInstruction {
+					addr,
+					mnemonic,
+				});
+			}
+		}
+		warnings
+	}
+
+	/// Checks that every `jmp`, `jt`, `jf` and `call` with a literal or label
+	/// target goes to the start of an instruction. Going anywhere in a block
+	/// of data is fine when `allow_data` is set.
+	pub fn check_jump_targets(&self, allow_data: bool) -> Vec<Warning> {
+		let mut addresses = self.instructions.keys().cloned().collect::<Vec<_>>();
+		addresses.sort_unstable();
+		let is_valid_target = |target: u16| {
+			let start = match addresses.binary_search(&target) {
+				Ok(_) if !allow_data => return true,
+				Ok(i) => addresses[i],
+				Err(0) => return false,
+				Err(i) => addresses[i - 1],
+			};
+			let instruction = &self.instructions[&start].instruction;
+			match instruction {
+				Instruction::Data(_) => allow_data && target - start < get_size(instruction),
+				_ => start == target,
+			}
+		};
+
+		let mut warnings = Vec::new();
+		for &addr in &addresses {
+			let instruction = &self.instructions[&addr].instruction;
+			let target = match instruction {
+				Instruction::Jmp(t)
+				| Instruction::Call(t)
+				| Instruction::Jt(_, t)
+				| Instruction::Jf(_, t) => t,
+				_ => continue,
+			};
+			let target = match target {
+				Token::Value(v) if *v < 32768 => *v,
+				Token::Label(name) => match self.labels.get(name) {
+					Some(&v) => v,
+					None => continue,
+				},
+				Token::Value(_) => continue,
+			};
+			if !is_valid_target(target) {
+				warnings.push(Warning::MisalignedJump {
+					addr,
+					mnemonic: instruction.mnemonic().unwrap_or("jump"),
+					target,
+				});
+			}
+		}
+		warnings
+	}
+
+	/// The address and line number of every instruction, in address order.
+	pub fn source_lines(&self) -> Vec<(u16, usize)> {
+		let mut lines = self
+			.instructions
+			.iter()
+			.map(|(&addr, parsed)| (addr, parsed.line_number))
+			.collect::<Vec<_>>();
+		lines.sort_unstable();
+		lines
+	}
+
+	/// The number of bytes the compiled binary takes up.
+	pub fn byte_size(&self) -> usize {
+		self.instructions
+			.values()
+			.map(|parsed| get_size(&parsed.instruction) as usize * 2)
+			.sum()
+	}
+
+	/// Where each `.section` ended up, in address order. Empty unless the
+	/// source has a `.section` directive.
+	pub fn sections(&self) -> &[Segment] {
+		&self.sections
+	}
+
+	/// Problems found while parsing that didn't stop the parsing.
+	pub fn warnings(&self) -> &[String] {
+		&self.warnings
+	}
 }
 
 type Constructor = Box<dyn Fn([Option<Token>; 3]) -> Result<Instruction, String>>;
@@ -55,12 +287,23 @@ pub fn parse<I: Read>(input: I) -> Result<Parsing, String> {
 
 	let mut instructions = HashMap::new();
 	let mut labels: HashMap<String, u16> = HashMap::new();
+	let mut warnings = Vec::new();
+	let mut lines = Vec::new();
 	let mut line = String::new();
 	let mut line_number = 1;
 	let mut pointer = 0;
+	let mut defined_labels = HashSet::new();
+	let mut section = None;
+	let mut section_starts = vec![(0, None)];
+	// Where the first `.org` is, since sections can't keep its address.
+	let mut first_org = None;
+	let mut defines: HashMap<String, u16> = HashMap::new();
+	let mut conditionals: Vec<Conditional> = Vec::new();
+	let mut block_comment = None;
 
 	let mut label: Option<String>;
-	let mut constructor: Option<Constructor>;
+	let mut constructor: Option<(usize, Constructor)>;
+	let mut data: Option<(usize, Vec<Token>)>;
 	let mut arguments: [Option<Token>; 3];
 	let mut argument_count: usize;
 	while reader
@@ -68,11 +311,190 @@ pub fn parse<I: Read>(input: I) -> Result<Parsing, String> {
 		.map_err(|_| format!("Error reading line {}!", line_number))?
 		> 0
 	{
+		lines.push(line.trim_end().to_string());
+		blank_block_comments(&mut line, &mut block_comment, line_number);
+
+		let directive = {
+			let mut words = words(&line);
+			match words.next() {
+				Some((column, d @ (".if" | ".else" | ".endif" | ".define"))) => {
+					let name = words.next().map(|(_, n)| n.to_string());
+					let value = words.next().map(|(_, v)| v.to_string());
+					Some((column, d, name, value))
+				}
+				_ => None,
+			}
+		};
+		let included = conditionals.iter().all(|c| c.active);
+		if let Some((column, directive, name, value)) = directive {
+			match (directive, name) {
+				(".if", Some(name)) => {
+					let taken = defines.get(&name).is_some_and(|&v| v != 0);
+					conditionals.push(Conditional {
+						line_number,
+						taken,
+						active: taken,
+						in_else: false,
+					});
+				}
+				(".else", _) => match conditionals.last_mut() {
+					Some(c) if !c.in_else => {
+						c.in_else = true;
+						c.active = !c.taken;
+					}
+					Some(_) => return Err(at(line_number, column, "Second .else for one .if!")),
+					None => return Err(at(line_number, column, ".else without an .if!")),
+				},
+				(".endif", _) => {
+					if conditionals.pop().is_none() {
+						return Err(at(line_number, column, ".endif without an .if!"));
+					}
+				}
+				(".define", Some(name)) if included => {
+					let value = match value.filter(|v| !v.starts_with('#')) {
+						Some(v) => v.parse::<u16>().map_err(|_| {
+							at(
+								line_number,
+								column,
+								format!("Can't define {} as \"{}\"!", name, v),
+							)
+						})?,
+						None => 1,
+					};
+					defines.insert(name, value);
+				}
+				(".define", Some(_)) => {}
+				(directive, _) => {
+					return Err(at(
+						line_number,
+						column,
+						format!("{} needs a name!", directive),
+					))
+				}
+			}
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+		if !included {
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+
+		if let Some(path) = line.trim().strip_prefix(".import") {
+			let path = path.trim().trim_matches('"');
+			let file = fs::File::open(path).map_err(|e| {
+				format!(
+					"Error when opening \"{}\" imported on line {}. {}",
+					path, line_number, e
+				)
+			})?;
+			labels.extend(read_symbols(file)?);
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+
+		if let Some(path) = line.trim().strip_prefix(".incbin") {
+			let path = path.trim().trim_matches('"');
+			let bytes = fs::read(path).map_err(|e| {
+				format!(
+					"Error when reading \"{}\" included on line {}. {}",
+					path, line_number, e
+				)
+			})?;
+			if bytes.len() % 2 != 0 {
+				return Err(format!(
+					"\"{}\" included on line {} has an odd byte length!",
+					path, line_number
+				));
+			}
+			let values = bytes
+				.chunks_exact(2)
+				.map(|word| Token::Value(u16::from_le_bytes([word[0], word[1]])))
+				.collect::<Vec<_>>();
+			let end = u16::try_from(values.len())
+				.ok()
+				.and_then(|size| u16::checked_add(pointer, size))
+				.ok_or_else(|| {
+					format!(
+						"\"{}\" included on line {} doesn't fit in memory!",
+						path, line_number
+					)
+				})?;
+			if !values.is_empty() {
+				instructions.insert(pointer, ParsedInstruction {
+					line_number,
+					instruction: Instruction::Data(values),
+					comment: None,
+				});
+			}
+			pointer = end;
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+
+		let (org, section_kind) = {
+			let mut directive = words(&line);
+			match directive.next() {
+				Some((column, ".org")) => (Some((column, org_arguments(directive))), None),
+				Some((column, ".section")) => (None, Some((column, section_argument(directive)))),
+				_ => (None, None),
+			}
+		};
+		if let Some((column, kind)) = section_kind {
+			let kind = kind.map_err(|e| at(line_number, column, e))?;
+			section = Some(kind);
+			section_starts.push((pointer, section));
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+		if let Some((column, arguments)) = org {
+			let (target, noops) = arguments.map_err(|e| at(line_number, column, e))?;
+			first_org.get_or_insert((line_number, column));
+			if target < pointer {
+				return Err(at(
+					line_number,
+					column,
+					format!(
+						"Can't move back to address {} from address {}!",
+						target, pointer
+					),
+				));
+			}
+			if noops {
+				for address in pointer..target {
+					instructions.insert(address, ParsedInstruction {
+						line_number,
+						instruction: Instruction::Noop(),
+						comment: None,
+					});
+				}
+			} else if target > pointer {
+				instructions.insert(pointer, ParsedInstruction {
+					line_number,
+					instruction: Instruction::Data(vec![
+						Token::Value(0);
+						(target - pointer) as usize
+					]),
+					comment: None,
+				});
+			}
+			pointer = target;
+			line_number += 1;
+			line.clear();
+			continue;
+		}
+
 		label = None;
 		constructor = None;
+		data = None;
 		arguments = [None, None, None];
 		argument_count = 0;
-		for part in line.split_whitespace() {
+		for (column, part) in words(&line) {
 			if part.starts_with('#') {
 				break;
 			} else if part.ends_with(':') {
@@ -80,26 +502,35 @@ pub fn parse<I: Read>(input: I) -> Result<Parsing, String> {
 					let name = &part[0..part.len() - 1];
 					if let Ok(pointer_label) = name.parse::<u16>() {
 						if pointer_label != pointer {
-							return Err(format!(
-								"Pointer label was {} but should have been {} on line {}.",
-								pointer_label, pointer, line_number
+							return Err(at(
+								line_number,
+								column,
+								format!(
+									"Pointer label {0} doesn't match current address {1}. Did you \
+									 forget a DATA directive? If you meant to advance the \
+									 pointer, use `.org {0}`.",
+									pointer_label, pointer
+								),
 							));
 						}
 					} else {
 						label = Some(String::from(name));
 					}
 				} else {
-					return Err(format!(
-						"Only one label per line! Detected a \":\" in an unusual place on line {}.",
-						line_number
+					return Err(at(
+						line_number,
+						column,
+						"Only one label per line! Detected a \":\" in an unusual place.",
 					));
 				}
-			} else if constructor.is_none() {
+			} else if constructor.is_none() && data.is_none() && part.eq_ignore_ascii_case("data") {
+				data = Some((column, Vec::new()));
+			} else if constructor.is_none() && data.is_none() {
 				constructor = match get_constructor(part) {
 					None => {
-						return Err(format!("Unknown op \"{}\" at line {}.", part, line_number))
+						return Err(at(line_number, column, format!("Unknown op \"{}\"", part)))
 					}
-					c => c,
+					c => c.map(|c| (column, c)),
 				};
 			} else {
 				let arg = if let Ok(value) = part.parse::<u16>() {
@@ -107,92 +538,409 @@ pub fn parse<I: Read>(input: I) -> Result<Parsing, String> {
 				} else {
 					Token::Label(String::from(part))
 				};
+				if let Some((_, values)) = &mut data {
+					values.push(arg);
+					continue;
+				}
+				if argument_count == arguments.len() {
+					return Err(at(
+						line_number,
+						column,
+						format!(
+							"Too many arguments! No instruction takes more than {}.",
+							arguments.len()
+						),
+					));
+				}
 				arguments[argument_count] = Some(arg);
 				argument_count += 1;
 			}
 		}
 
 		if let Some(label_name) = label {
+			if let Some(org) = org_annotation(&line).filter(|&o| o != pointer) {
+				warnings.push(format!(
+					"Label \"{}\" at line {} is annotated with `.org {}` but is at address {}.",
+					label_name, line_number, org, pointer
+				));
+			}
+			defined_labels.insert(label_name.clone());
 			labels.insert(label_name, pointer);
 		}
 
-		if let Some(con) = constructor {
-			let instruction = con(arguments)?;
+		let instruction = match (data, constructor) {
+			(Some((column, values)), _) if values.is_empty() => {
+				return Err(at(line_number, column, "data needs at least one value!"));
+			}
+			(Some((column, values)), _) => Some((column, Instruction::Data(values))),
+			(None, Some((column, con))) => Some((
+				column,
+				con(arguments).map_err(|e| at(line_number, column, e))?,
+			)),
+			(None, None) => None,
+		};
+		if let Some((column, instruction)) = instruction {
+			let misplaced = match (section, &instruction) {
+				(Some(SegmentKind::Code), Instruction::Data(_)) => {
+					Some("Data can't go in a code section!")
+				}
+				(Some(SegmentKind::Data), Instruction::Data(_)) => None,
+				(Some(SegmentKind::Data), _) => Some("Only data can go in a data section!"),
+				_ => None,
+			};
+			if let Some(message) = misplaced {
+				return Err(at(line_number, column, message));
+			}
 			let size = get_size(&instruction);
 			instructions.insert(pointer, ParsedInstruction {
 				line_number,
 				instruction,
+				comment: comment(&line).map(String::from),
 			});
-			pointer += size;
+			pointer = pointer.checked_add(size).ok_or_else(|| {
+				format!("Line {} is past the end of the address space!", line_number)
+			})?;
 		}
 
 		line_number += 1;
 		line.clear();
 	}
 
-	Ok(Parsing {
+	if let Some(opened) = block_comment {
+		return Err(format!(
+			"The block comment on line {} is never closed!",
+			opened
+		));
+	}
+	if let Some(c) = conditionals.first() {
+		return Err(format!(
+			"The .if on line {} is never closed!",
+			c.line_number
+		));
+	}
+
+	let sections = if section_starts.len() > 1 {
+		if let Some((line_number, column)) = first_org {
+			return Err(at(
+				line_number,
+				column,
+				".org can't be used with .section, the sections are moved after it's applied!",
+			));
+		}
+		relocate_sections(
+			&mut instructions,
+			&mut labels,
+			&defined_labels,
+			&section_starts,
+			pointer,
+		)
+	} else {
+		Vec::new()
+	};
+	let parsing = Parsing {
 		instructions,
 		labels,
-	})
+		warnings,
+		lines,
+		sections,
+	};
+	parsing.validate_labels().map_err(|errors| {
+		errors
+			.iter()
+			.map(|e| e.to_string())
+			.collect::<Vec<_>>()
+			.join("\n")
+	})?;
+	Ok(parsing)
+}
+
+/// An `.if` that hasn't reached its `.endif` yet.
+struct Conditional {
+	line_number: usize,
+	/// Whether the name was defined and nonzero.
+	taken: bool,
+	/// Whether the lines in the current branch are included, ignoring any
+	/// outer `.if`.
+	active: bool,
+	in_else: bool,
+}
+
+/// Splits a line into its whitespace separated words, each with the column,
+/// counted in bytes from 1, that it starts at.
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+	let mut start = None;
+	line.char_indices()
+		.chain(iter::once((line.len(), ' ')))
+		.filter_map(move |(i, c)| match (c.is_whitespace(), start) {
+			(false, None) => {
+				start = Some(i);
+				None
+			}
+			(true, Some(s)) => {
+				start = None;
+				Some((s + 1, &line[s..i]))
+			}
+			_ => None,
+		})
 }
 
+/// Replaces everything between `/*` and `*/` in `line` with spaces, so that
+/// columns stay in place. `open` holds the line number of a block comment
+/// still open from an earlier line. Block comments don't nest, the first
+/// `*/` closes one, and a `/*` after a `#` comment has started is ignored.
+fn blank_block_comments(line: &mut String, open: &mut Option<usize>, line_number: usize) {
+	let mut bytes = std::mem::take(line).into_bytes();
+	let mut i = 0;
+	while i < bytes.len() {
+		let pair = &bytes[i..(i + 2).min(bytes.len())];
+		if open.is_some() {
+			if pair == b"*/" {
+				*open = None;
+				bytes[i..i + 2].fill(b' ');
+				i += 2;
+				continue;
+			}
+			if !bytes[i].is_ascii_whitespace() {
+				bytes[i] = b' ';
+			}
+		} else if pair == b"/*" {
+			*open = Some(line_number);
+			bytes[i..i + 2].fill(b' ');
+			i += 2;
+			continue;
+		} else if bytes[i] == b'#' && (i == 0 || bytes[i - 1].is_ascii_whitespace()) {
+			break;
+		}
+		i += 1;
+	}
+	*line = String::from_utf8(bytes).expect("Only whole characters are blanked.");
+}
+
+/// Reads the `<addr> [zero|noop]` after `.org`, returning the address and
+/// whether to pad up to it with `noop`s rather than zero words.
+fn org_arguments<'a>(
+	mut arguments: impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(u16, bool), String> {
+	let target = arguments
+		.next()
+		.and_then(|(_, a)| a.parse::<u16>().ok())
+		.ok_or_else(|| ".org needs an address!".to_string())?;
+	let fill = arguments
+		.next()
+		.map(|(_, f)| f)
+		.filter(|f| !f.starts_with('#'));
+	let noops = match fill {
+		None | Some("zero") => false,
+		Some("noop") => true,
+		Some(f) => return Err(format!("Unknown .org padding \"{}\", use zero or noop.", f)),
+	};
+	Ok((target, noops))
+}
+
+fn section_argument<'a>(
+	mut arguments: impl Iterator<Item = (usize, &'a str)>,
+) -> Result<SegmentKind, String> {
+	match arguments.next().map(|(_, kind)| kind) {
+		Some("code") => Ok(SegmentKind::Code),
+		Some("data") => Ok(SegmentKind::Data),
+		_ => Err(".section needs a kind, code or data!".to_string()),
+	}
+}
+
+/// Moves every data section after the code, keeping the order within each
+/// kind, and moves the labels defined in the source along. Anything before
+/// the first `.section` counts as code. Literal addresses aren't changed, so
+/// anything that points past a data section should use a label, and `.org`
+/// is rejected before this. Returns the new sections in address order.
+fn relocate_sections(
+	instructions: &mut HashMap<u16, ParsedInstruction>,
+	labels: &mut HashMap<String, u16>,
+	defined_labels: &HashSet<String>,
+	section_starts: &[(u16, Option<SegmentKind>)],
+	end: u16,
+) -> Vec<Segment> {
+	let mut blocks = section_starts
+		.iter()
+		.enumerate()
+		.map(|(i, &(start, kind))| {
+			let block_end = section_starts.get(i + 1).map_or(end, |&(s, _)| s);
+			(start, block_end, kind.unwrap_or(SegmentKind::Code))
+		})
+		.filter(|&(start, block_end, _)| start < block_end)
+		.collect::<Vec<_>>();
+	blocks.sort_by_key(|&(_, _, kind)| kind == SegmentKind::Data);
+
+	let mut moves = Vec::new();
+	let mut sections = Vec::new();
+	let mut new_start = 0;
+	for (start, block_end, kind) in blocks {
+		moves.push((start, block_end, new_start));
+		sections.push(Segment {
+			start: new_start as usize,
+			end: (new_start + block_end - start) as usize,
+			kind,
+		});
+		new_start += block_end - start;
+	}
+	let relocate = |addr: u16| {
+		moves
+			.iter()
+			.find(|&&(start, block_end, _)| start <= addr && addr < block_end)
+			.map_or(addr, |&(start, _, new_start)| addr - start + new_start)
+	};
+
+	*instructions = instructions
+		.drain()
+		.map(|(addr, parsed)| (relocate(addr), parsed))
+		.collect();
+	for (name, addr) in labels.iter_mut() {
+		if defined_labels.contains(name) {
+			*addr = relocate(*addr);
+		}
+	}
+	sections
+}
+
+/// Prefixes a parse error with where it was found.
+fn at<M: fmt::Display>(line_number: usize, column: usize, message: M) -> String {
+	format!("line {}, column {}: {}", line_number, column, message)
+}
+
+/// Splits a line into its code and the comment after a `#` that starts a
+/// word, both trimmed.
+pub(super) fn split_comment(line: &str) -> (&str, Option<&str>) {
+	match line
+		.char_indices()
+		.find(|&(i, c)| c == '#' && line[..i].chars().last().is_none_or(char::is_whitespace))
+	{
+		Some((i, _)) => (line[..i].trim_end(), Some(line[i + 1..].trim())),
+		None => (line.trim_end(), None),
+	}
+}
+
+fn comment(line: &str) -> Option<&str> {
+	split_comment(line).1
+}
+
+/// Finds a `.org <addr>` annotation in the comment of a line.
+fn org_annotation(line: &str) -> Option<u16> {
+	let mut words = comment(line)?.split_whitespace();
+	words.find(|&w| w == ".org")?;
+	words.next()?.parse().ok()
+}
+
+impl Instruction {
+	/// The opcode of this instruction, `None` for data and `sub`.
+	pub(super) fn opcode(&self) -> Option<u16> {
+		match self {
+			Instruction::Halt() => Some(0),
+			Instruction::Set(_, _) => Some(1),
+			Instruction::Push(_) => Some(2),
+			Instruction::Pop(_) => Some(3),
+			Instruction::Eq(_, _, _) => Some(4),
+			Instruction::Gt(_, _, _) => Some(5),
+			Instruction::Jmp(_) => Some(6),
+			Instruction::Jt(_, _) => Some(7),
+			Instruction::Jf(_, _) => Some(8),
+			Instruction::Add(_, _, _) => Some(9),
+			Instruction::Mult(_, _, _) => Some(10),
+			Instruction::Mod(_, _, _) => Some(11),
+			Instruction::And(_, _, _) => Some(12),
+			Instruction::Or(_, _, _) => Some(13),
+			Instruction::Not(_, _) => Some(14),
+			Instruction::RMem(_, _) => Some(15),
+			Instruction::WMem(_, _) => Some(16),
+			Instruction::Call(_) => Some(17),
+			Instruction::Ret() => Some(18),
+			Instruction::Out(_) => Some(19),
+			Instruction::In(_) => Some(20),
+			Instruction::Noop() => Some(21),
+			Instruction::Sub(_, _, _) | Instruction::Data(_) => None,
+		}
+	}
+
+	/// The mnemonic of this instruction, `None` for data.
+	pub(super) fn mnemonic(&self) -> Option<&'static str> {
+		match self {
+			Instruction::Sub(_, _, _) => Some("sub"),
+			_ => self.opcode().and_then(opcode::mnemonic),
+		}
+	}
+
+	/// The arguments of this instruction, in order.
+	pub(super) fn tokens(&self) -> Vec<&Token> {
+		match self {
+			Instruction::Halt() | Instruction::Ret() | Instruction::Noop() => vec![],
+			Instruction::Push(a)
+			| Instruction::Pop(a)
+			| Instruction::Jmp(a)
+			| Instruction::Call(a)
+			| Instruction::Out(a)
+			| Instruction::In(a) => vec![a],
+			Instruction::Set(a, b)
+			| Instruction::Jt(a, b)
+			| Instruction::Jf(a, b)
+			| Instruction::Not(a, b)
+			| Instruction::RMem(a, b)
+			| Instruction::WMem(a, b) => vec![a, b],
+			Instruction::Eq(a, b, c)
+			| Instruction::Gt(a, b, c)
+			| Instruction::Add(a, b, c)
+			| Instruction::Mult(a, b, c)
+			| Instruction::Mod(a, b, c)
+			| Instruction::And(a, b, c)
+			| Instruction::Or(a, b, c)
+			| Instruction::Sub(a, b, c) => vec![a, b, c],
+			Instruction::Data(values) => values.iter().collect(),
+		}
+	}
+}
+
+/// `sub` is expanded to a `not` and two `add`s when compiled.
+const SUB_SIZE: u16 = 3 + 4 + 4;
+
 pub(super) fn get_size(instruction: &Instruction) -> u16 {
 	match instruction {
-		Instruction::Halt() => 1,
-		Instruction::Set(_, _) => 3,
-		Instruction::Push(_) => 2,
-		Instruction::Pop(_) => 2,
-		Instruction::Eq(_, _, _) => 4,
-		Instruction::Gt(_, _, _) => 4,
-		Instruction::Jmp(_) => 2,
-		Instruction::Jt(_, _) => 3,
-		Instruction::Jf(_, _) => 3,
-		Instruction::Add(_, _, _) => 4,
-		Instruction::Mult(_, _, _) => 4,
-		Instruction::Mod(_, _, _) => 4,
-		Instruction::And(_, _, _) => 4,
-		Instruction::Or(_, _, _) => 4,
-		Instruction::Not(_, _) => 3,
-		Instruction::RMem(_, _) => 3,
-		Instruction::WMem(_, _) => 3,
-		Instruction::Call(_) => 2,
-		Instruction::Ret() => 1,
-		Instruction::Out(_) => 2,
-		Instruction::In(_) => 2,
-		Instruction::Noop() => 1,
-		Instruction::Data(_) => 1,
+		Instruction::Sub(_, _, _) => SUB_SIZE,
+		Instruction::Data(values) => u16::try_from(values.len()).unwrap_or(u16::MAX),
+		_ => instruction
+			.opcode()
+			.and_then(opcode::size)
+			.map_or(1, |s| s as u16),
 	}
 }
 
 fn get_constructor(op: &str) -> Option<Constructor> {
-	match op {
-		"halt" => Some(Box::new(halt)),
-		"set" => Some(Box::new(set)),
-		"push" => Some(Box::new(push)),
-		"pop" => Some(Box::new(pop)),
-		"eq" => Some(Box::new(eq)),
-		"gt" => Some(Box::new(gt)),
-		"jmp" => Some(Box::new(jmp)),
-		"jt" => Some(Box::new(jt)),
-		"jf" => Some(Box::new(jf)),
-		"add" => Some(Box::new(add)),
-		"mult" => Some(Box::new(mult)),
-		"mod" => Some(Box::new(mod_op)),
-		"and" => Some(Box::new(and)),
-		"or" => Some(Box::new(or)),
-		"not" => Some(Box::new(not)),
-		"rmem" => Some(Box::new(rmem)),
-		"wmem" => Some(Box::new(wmem)),
-		"call" => Some(Box::new(call)),
-		"ret" => Some(Box::new(ret)),
-		"out" => Some(Box::new(out)),
-		"in" => Some(Box::new(in_op)),
-		"noop" => Some(Box::new(noop)),
+	let op = op.to_ascii_lowercase();
+	match opcode::from_mnemonic(&op) {
+		Some(0) => Some(Box::new(halt)),
+		Some(1) => Some(Box::new(set)),
+		Some(2) => Some(Box::new(push)),
+		Some(3) => Some(Box::new(pop)),
+		Some(4) => Some(Box::new(eq)),
+		Some(5) => Some(Box::new(gt)),
+		Some(6) => Some(Box::new(jmp)),
+		Some(7) => Some(Box::new(jt)),
+		Some(8) => Some(Box::new(jf)),
+		Some(9) => Some(Box::new(add)),
+		Some(10) => Some(Box::new(mult)),
+		Some(11) => Some(Box::new(mod_op)),
+		Some(12) => Some(Box::new(and)),
+		Some(13) => Some(Box::new(or)),
+		Some(14) => Some(Box::new(not)),
+		Some(15) => Some(Box::new(rmem)),
+		Some(16) => Some(Box::new(wmem)),
+		Some(17) => Some(Box::new(call)),
+		Some(18) => Some(Box::new(ret)),
+		Some(19) => Some(Box::new(out)),
+		Some(20) => Some(Box::new(in_op)),
+		Some(21) => Some(Box::new(noop)),
+		None if op == "sub" => Some(Box::new(sub)),
 		_ => {
 			if let Ok(v) = op.parse() {
 				Some(Box::new(move |args: [Option<Token>; 3]| {
 					if [None, None, None] == args {
-						Ok(Instruction::Data(Token::Value(v)))
+						Ok(Instruction::Data(vec![Token::Value(v)]))
 					} else {
 						Err("Only one data point per line!".to_string())
 					}
@@ -379,3 +1127,430 @@ fn noop(args: [Option<Token>; 3]) -> Result<Instruction, String> {
 		Err("noop takes no arguments".to_string())
 	}
 }
+
+fn sub(args: [Option<Token>; 3]) -> Result<Instruction, String> {
+	if let [Some(a1), Some(a2), Some(a3)] = args {
+		Ok(Instruction::Sub(a1, a2, a3))
+	} else {
+		Err("sub takes three arguments".to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::compile;
+
+	#[test]
+	fn keep_comment() {
+		let parsing = parse("noop # spin\nhalt\n".as_bytes()).unwrap();
+		assert_eq!(parsing.instructions[&0].comment, Some("spin".to_string()));
+		assert_eq!(parsing.instructions[&1].comment, None);
+
+		let mut with_comment = Vec::new();
+		compile(&parsing, &mut with_comment).unwrap();
+		let mut without_comment = Vec::new();
+		compile(
+			&parse("noop\nhalt\n".as_bytes()).unwrap(),
+			&mut without_comment,
+		)
+		.unwrap();
+		assert_eq!(
+			with_comment, without_comment,
+			"Comments should not affect the binary."
+		);
+	}
+
+	#[test]
+	fn label_layouts() {
+		let parsing = parse("noop\nstart: jmp start\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.labels["start"], 1,
+			"A label should point to the instruction on its line."
+		);
+		let parsing = parse("noop\nstart:\n\nhalt\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.labels["start"], 1,
+			"A label by itself should point to the next instruction."
+		);
+	}
+
+	#[test]
+	fn data_sizes() {
+		let parsing = parse("65\ndata 1 2 3\n.org 10\nhalt\n".as_bytes()).unwrap();
+		let sizes = [0, 1, 4, 10]
+			.iter()
+			.map(|a| get_size(&parsing.instructions[a].instruction))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			sizes,
+			vec![1, 3, 6, 1],
+			"Each data node is as large as its values."
+		);
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		assert_eq!(binary.len(), 11 * 2);
+	}
+
+	#[test]
+	fn label_addresses() {
+		let parsing = parse("noop\nstart: out 65\nend: halt\n".as_bytes()).unwrap();
+		assert_eq!(parsing.label_address("start"), Some(1));
+		assert_eq!(parsing.label_address("missing"), None);
+		let mut labels = parsing.labels().collect::<Vec<_>>();
+		labels.sort_unstable();
+		assert_eq!(labels, vec![("end", 3), ("start", 1)]);
+	}
+
+	#[test]
+	fn byte_size() {
+		let parsing = parse(
+			"start: out 65
+sub 32768 32768 1
+data 1 2
+jmp start
+"
+			.as_bytes(),
+		)
+		.unwrap();
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		assert_eq!(parsing.byte_size(), (2 + 11 + 2 + 2) * 2);
+		assert_eq!(
+			parsing.byte_size(),
+			binary.len(),
+			"The size should match the binary."
+		);
+	}
+
+	#[test]
+	fn org_padding() {
+		let mut binary = Vec::new();
+		compile(&parse(".org 4\nout 65\n".as_bytes()).unwrap(), &mut binary).unwrap();
+		assert_eq!(binary, vec![0, 0, 0, 0, 0, 0, 0, 0, 19, 0, 65, 0]);
+
+		let mut binary = Vec::new();
+		let parsing = parse("halt\n.org 3 noop # pad\nstart: jmp start\n".as_bytes()).unwrap();
+		compile(&parsing, &mut binary).unwrap();
+		assert_eq!(binary, vec![0, 0, 21, 0, 21, 0, 6, 0, 3, 0]);
+		assert_eq!(parsing.label_address("start"), Some(3));
+
+		assert_eq!(
+			parse("noop\nnoop\n.org 1\n".as_bytes()).map(|_| ()),
+			Err("line 3, column 1: Can't move back to address 1 from address 2!".to_string())
+		);
+		assert_eq!(
+			parse("  .org 1 nop\n".as_bytes()).map(|_| ()),
+			Err("line 1, column 3: Unknown .org padding \"nop\", use zero or noop.".to_string())
+		);
+	}
+
+	#[test]
+	fn sections() {
+		let source = [
+			"jmp start",
+			".section data",
+			"message: data 72 105",
+			".section code",
+			"start: out 65",
+			"halt",
+			".section data",
+			"end: 10",
+		];
+		let parsing = parse(source.join("\n").as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		assert_eq!(
+			binary,
+			vec![6, 0, 2, 0, 19, 0, 65, 0, 0, 0, 72, 0, 105, 0, 10, 0],
+			"Code should come before data."
+		);
+		assert_eq!(parsing.label_address("start"), Some(2));
+		assert_eq!(parsing.label_address("message"), Some(5));
+		assert_eq!(parsing.label_address("end"), Some(7));
+		let kinds = parsing
+			.sections()
+			.iter()
+			.map(|s| (s.start, s.end, s.kind))
+			.collect::<Vec<_>>();
+		assert_eq!(kinds, vec![
+			(0, 2, SegmentKind::Code),
+			(2, 5, SegmentKind::Code),
+			(5, 7, SegmentKind::Data),
+			(7, 8, SegmentKind::Data),
+		]);
+
+		assert_eq!(
+			parse(".section data\n  halt\n".as_bytes()).map(|_| ()),
+			Err("line 2, column 3: Only data can go in a data section!".to_string())
+		);
+		assert_eq!(
+			parse(".section code\n65\n".as_bytes()).map(|_| ()),
+			Err("line 2, column 1: Data can't go in a code section!".to_string())
+		);
+		assert_eq!(
+			parse(
+				".section data
+data 1
+.section code
+.org 5
+halt
+"
+				.as_bytes()
+			)
+			.map(|_| ()),
+			Err(
+				"line 4, column 1: .org can't be used with .section, the sections are moved after \
+				 it's applied!"
+					.to_string()
+			),
+			"The .org address would be lost when the code moves in front of the data."
+		);
+	}
+
+	#[test]
+	fn include_binary() {
+		let path = std::env::temp_dir().join("synacor_include_binary.bin");
+		fs::write(&path, [1, 0, 0x34, 0x12, 0xFF, 0x7F]).unwrap();
+		let source = format!("noop\n.incbin \"{}\"\nend: halt\n", path.display());
+		let parsing = parse(source.as_bytes());
+		fs::write(&path, [1, 0, 2]).unwrap();
+		let odd = parse(format!(".incbin {}\n", path.display()).as_bytes()).map(|_| ());
+		fs::remove_file(&path).unwrap();
+
+		let parsing = parsing.unwrap();
+		assert_eq!(parsing.label_address("end"), Some(4));
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		assert_eq!(binary, vec![21, 0, 1, 0, 0x34, 0x12, 0xFF, 0x7F, 0, 0]);
+		assert_eq!(
+			odd,
+			Err(format!(
+				"\"{}\" included on line 1 has an odd byte length!",
+				path.display()
+			))
+		);
+	}
+
+	#[test]
+	fn block_comments() {
+		let source = [
+			"out 65 /* start",
+			"halt",
+			"# still a comment */ out 66",
+			"/* one line */ out 67 # done",
+			"",
+		];
+		let parsing = parse(source.join("\n").as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		super::super::compile(&parsing, &mut binary).unwrap();
+		assert_eq!(
+			binary,
+			[19, 0, 65, 0, 19, 0, 66, 0, 19, 0, 67, 0],
+			"Only the code around the comments should be parsed."
+		);
+
+		assert_eq!(
+			parse("noop\n/* a\n/* b */ out 65\n".as_bytes())
+				.unwrap()
+				.byte_size(),
+			6,
+			"Block comments shouldn't nest."
+		);
+		assert_eq!(
+			parse("noop\n  /* open\nhalt\n".as_bytes()).map(|_| ()),
+			Err("The block comment on line 2 is never closed!".to_string())
+		);
+	}
+
+	#[test]
+	fn conditional_assembly() {
+		let source = |define: &str| {
+			[
+				define,
+				".if FLAG",
+				"out 65",
+				".if OTHER",
+				"out 66",
+				".else",
+				"out 67",
+				".endif",
+				".else",
+				"out 68",
+				".endif",
+				"halt",
+			]
+			.join("\n")
+		};
+		let assemble = |source: String| {
+			let mut binary = Vec::new();
+			compile(&parse(source.as_bytes()).unwrap(), &mut binary).unwrap();
+			binary
+		};
+		assert_eq!(
+			assemble(source(".define FLAG")),
+			vec![19, 0, 65, 0, 19, 0, 67, 0, 0, 0],
+			"The .if FLAG block should be included when FLAG is defined."
+		);
+		assert_eq!(
+			assemble(source(".define FLAG 0")),
+			vec![19, 0, 68, 0, 0, 0],
+			"A zero FLAG counts as undefined."
+		);
+		assert_eq!(assemble(source("")), vec![19, 0, 68, 0, 0, 0]);
+
+		assert_eq!(
+			parse("noop\n  .endif\n".as_bytes()).map(|_| ()),
+			Err("line 2, column 3: .endif without an .if!".to_string())
+		);
+		assert_eq!(
+			parse(".if FLAG\nnoop\n".as_bytes()).map(|_| ()),
+			Err("The .if on line 1 is never closed!".to_string())
+		);
+	}
+
+	#[test]
+	fn undefined_labels() {
+		let source = "start: jmp middle\nmiddle: call nowhere\njt 32768 start\njf 32768 gone\n";
+		assert_eq!(
+			parse(source.as_bytes()).map(|_| ()),
+			Err([
+				"Undefined label \"nowhere\" at line 2.",
+				"Undefined label \"gone\" at line 4.",
+			]
+			.join("\n")),
+			"Every undefined label should be reported."
+		);
+	}
+
+	#[test]
+	fn unreachable_after_halt() {
+		let parsing = parse("halt\nnoop\nhalt\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.lint(),
+			vec![Warning::UnreachableInstruction {
+				addr: 1,
+				mnemonic: "noop",
+			}],
+			"Only the noop right after the first halt is unreachable."
+		);
+
+		let parsing = parse("jmp 3\nhere: noop\n3: out 65\njmp here\n7\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.lint(),
+			vec![],
+			"Labels, jump targets and data are fine."
+		);
+	}
+
+	#[test]
+	fn jump_into_instruction() {
+		let parsing =
+			parse("start: set 32768 1\njmp 1\njt 32768 start\nhalt\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.check_jump_targets(false),
+			vec![Warning::MisalignedJump {
+				addr: 3,
+				mnemonic: "jmp",
+				target: 1,
+			}],
+			"Jumping into the middle of the set should warn."
+		);
+
+		let parsing = parse("jmp 5\nhalt\nret\ndata 1 2\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.check_jump_targets(true),
+			vec![],
+			"Data targets should be fine when allowed."
+		);
+		assert_eq!(parsing.check_jump_targets(false).len(), 1);
+	}
+
+	#[test]
+	fn pointer_label_mismatch() {
+		let result = parse("noop\n2: halt\n".as_bytes());
+		assert_eq!(
+			result.map(|_| ()),
+			Err(
+				"line 2, column 1: Pointer label 2 doesn't match current address 1. Did you \
+				 forget a DATA directive? If you meant to advance the pointer, use `.org 2`."
+					.to_string()
+			),
+		);
+	}
+
+	#[test]
+	fn too_many_arguments() {
+		assert_eq!(
+			parse("add 1 2 3 4\n".as_bytes()).map(|_| ()),
+			Err(
+				"line 1, column 11: Too many arguments! No instruction takes more than 3."
+					.to_string()
+			),
+		);
+	}
+
+	#[test]
+	fn error_columns() {
+		let cases = [
+			("noop\n    foo 1\n", "line 2, column 5: Unknown op \"foo\""),
+			("\tadd 1 2\n", "line 1, column 2: add takes three arguments"),
+			(
+				"a: b: noop\n",
+				"line 1, column 4: Only one label per line! Detected a \":\" in an unusual place.",
+			),
+		];
+		for (source, expected) in cases.iter() {
+			assert_eq!(
+				parse(source.as_bytes()).map(|_| ()),
+				Err(expected.to_string()),
+				"Parsing {:?}.",
+				source
+			);
+		}
+	}
+
+	#[test]
+	fn mnemonics_ignore_case() {
+		let source = "HALT\nAdd 32768 1 2\nStart: jMP Start\nnoop\nDATA 1 2\n";
+		let parsing = parse(source.as_bytes()).unwrap();
+		let opcodes = [0, 1, 5, 7]
+			.iter()
+			.map(|a| parsing.instructions[a].instruction.opcode())
+			.collect::<Vec<_>>();
+		assert_eq!(opcodes, vec![Some(0), Some(9), Some(6), Some(21)]);
+		let mut binary = Vec::new();
+		compile(&parsing, &mut binary).unwrap();
+		let mut lowercase = Vec::new();
+		compile(
+			&parse("halt\nadd 32768 1 2\nStart: jmp Start\nnoop\ndata 1 2\n".as_bytes()).unwrap(),
+			&mut lowercase,
+		)
+		.unwrap();
+		assert_eq!(binary, lowercase, "Arguments should parse the same.");
+		assert_eq!(binary[16..], [1, 0, 2, 0], "DATA should be read as data.");
+		assert_eq!(parsing.label_address("Start"), Some(5));
+		assert_eq!(
+			parsing.label_address("start"),
+			None,
+			"Labels should keep their case."
+		);
+		assert_eq!(
+			parse("unknown\n".as_bytes()).map(|_| ()),
+			Err("line 1, column 1: Unknown op \"unknown\"".to_string())
+		);
+	}
+
+	#[test]
+	fn org_annotation_mismatch() {
+		let parsing = parse("noop\nstart: halt # .org 1\nend: halt # .org 1\n".as_bytes()).unwrap();
+		assert_eq!(
+			parsing.warnings(),
+			&[
+				"Label \"end\" at line 3 is annotated with `.org 1` but is at address 2."
+					.to_string()
+			],
+			"Only the mismatching annotation should warn."
+		);
+	}
+}
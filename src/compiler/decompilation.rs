@@ -1,241 +1,609 @@
-use std::io::{self, Write};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt,
+	io::{self, Write},
+};
 
-type Handler<O> = fn(&[u16], usize, &mut O) -> io::Result<usize>;
+use super::disasm::{decode, DecodedInstruction, Disassembler};
+use crate::opcode;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentKind {
+	Code,
+	Data,
+}
+
+/// A run of memory, from `start` up to but not including `end`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+	pub start: usize,
+	pub end: usize,
+	pub kind: SegmentKind,
+}
 
 pub fn decompile<O: Write>(memory: &[u16], out: &mut O) -> Result<(), String> {
+	decompile_with_leaders(memory, out, &HashSet::new(), &HashMap::new())
+}
+
+/// Decompiles like [`decompile`], but starts each basic block with a blank
+/// line and a `# block <n>` comment.
+pub fn decompile_blocks<O: Write>(memory: &[u16], out: &mut O) -> Result<(), String> {
+	decompile_with_leaders(memory, out, &find_leaders(memory), &HashMap::new())
+}
+
+/// Decompiles like [`decompile`], but names addresses with the labels of a
+/// symbol table, such as one read by [`super::symbols::read_symbols`]. Each
+/// label gets a `name:` line before its address, and jumps and calls to it
+/// use the name instead of the address.
+pub fn decompile_with_symbols<O: Write>(
+	memory: &[u16],
+	symbols: &HashMap<String, u16>,
+	out: &mut O,
+) -> Result<(), String> {
+	let mut names: HashMap<usize, Vec<&str>> = HashMap::new();
+	for (name, &addr) in symbols {
+		names.entry(addr as usize).or_default().push(name);
+	}
+	for labels in names.values_mut() {
+		labels.sort_unstable();
+	}
+	decompile_with_leaders(memory, out, &HashSet::new(), &names)
+}
+
+const ADDRESS_WIDTH: usize = 6;
+const MNEMONIC_WIDTH: usize = 8;
+
+/// Decompiles like [`decompile`], but aligns the columns with spaces instead
+/// of tabs. Tabs are expanded to stops every `tab_width` columns, except that
+/// the address column is at least 6 wide and the mnemonic column at least 8,
+/// so a `tab_width` of 1 gives those fixed widths. Operands are separated by
+/// single spaces.
+pub fn decompile_aligned<O: Write>(
+	memory: &[u16],
+	out: &mut O,
+	tab_width: usize,
+) -> Result<(), String> {
+	let mut tabbed = Vec::new();
+	decompile(memory, &mut tabbed)?;
+	align_columns(&tabbed, tab_width.max(1), false, out)
+}
+
+const GRAY: &str = "\x1b[90m";
+const CYAN: &str = "\x1b[36m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BOLD_WHITE: &str = "\x1b[1;37m";
+const RESET: &str = "\x1b[0m";
+
+/// Decompiles like [`decompile`], but when `use_color` is set, wraps each
+/// column in ANSI color codes: addresses in gray, mnemonics in cyan,
+/// registers in green, literals in yellow and labels in bold white.
+pub fn decompile_colored<O: Write>(
+	memory: &[u16],
+	out: &mut O,
+	use_color: bool,
+) -> Result<(), String> {
+	let mut tabbed = Vec::new();
+	decompile(memory, &mut tabbed)?;
+	align_columns(&tabbed, 0, use_color, out)
+}
+
+/// Rewrites the tab separated columns of decompiled text, such as from
+/// [`decompile_blocks`] or [`decompile_with_symbols`]. A `tab_width` above 0
+/// aligns them with spaces like [`decompile_aligned`], and 0 keeps the tabs.
+/// `use_color` colors them like [`decompile_colored`].
+pub fn align_columns<O: Write>(
+	tabbed: &[u8],
+	tab_width: usize,
+	use_color: bool,
+	out: &mut O,
+) -> Result<(), String> {
+	let paint = |index: usize, column: &str| {
+		if use_color && !column.is_empty() {
+			format!("{}{}{}", column_color(index, column), column, RESET)
+		} else {
+			column.to_string()
+		}
+	};
+	for tabbed_line in String::from_utf8_lossy(tabbed).lines() {
+		let mut line = String::new();
+		// How wide the line is, not counting color codes.
+		let mut width = 0;
+		for (index, column) in tabbed_line.split('\t').enumerate() {
+			if index > 0 && tab_width == 0 {
+				line.push('\t');
+			} else if index > 0 {
+				let stop = (width / tab_width + 1) * tab_width;
+				let padded = match index {
+					1 => stop.max(ADDRESS_WIDTH),
+					2 => stop.max(ADDRESS_WIDTH + MNEMONIC_WIDTH),
+					_ => width + 1,
+				};
+				line.extend(std::iter::repeat_n(' ', padded - width));
+				width = padded;
+			}
+			line.push_str(&paint(index, column));
+			width += column.len();
+		}
+		writeln!(out, "{}", line).map_err(could_not_write)?;
+	}
+	Ok(())
+}
+
+/// The color of the column at `index` of a decompiled line.
+fn column_color(index: usize, column: &str) -> &'static str {
+	match (index, column.parse::<u16>()) {
+		(0, _) => GRAY,
+		// Data is written as a number where the mnemonic would be.
+		(1, Ok(_)) => YELLOW,
+		(1, Err(_)) => CYAN,
+		(_, Ok(32768..=32775)) => GREEN,
+		(_, Ok(_)) => YELLOW,
+		(_, Err(_)) => BOLD_WHITE,
+	}
+}
+
+/// Writes `memory` as a Rust constant named `const_name`, eight `0x` words
+/// to a line, to embed a binary in Rust code.
+pub fn decompile_rust<O: Write>(
+	memory: &[u16],
+	const_name: &str,
+	out: &mut O,
+) -> Result<(), String> {
+	writeln!(out, "pub const {}: &[u16] = &[", const_name).map_err(could_not_write)?;
+	for words in memory.chunks(8) {
+		let words = words
+			.iter()
+			.map(|w| format!("{:#06x},", w))
+			.collect::<Vec<_>>();
+		writeln!(out, "\t{}", words.join(" ")).map_err(could_not_write)?;
+	}
+	writeln!(out, "];").map_err(could_not_write)
+}
+
+fn decompile_with_leaders<O: Write>(
+	memory: &[u16],
+	out: &mut O,
+	leaders: &HashSet<usize>,
+	names: &HashMap<usize, Vec<&str>>,
+) -> Result<(), String> {
+	let write_labels = |out: &mut O, pointer: usize| {
+		for name in names.get(&pointer).into_iter().flatten() {
+			writeln!(out, "{}:", name).map_err(could_not_write)?;
+		}
+		Ok::<_, String>(())
+	};
+	let mut block = 0;
+	for segment in classify(memory) {
+		if segment.kind == SegmentKind::Data {
+			for (pointer, word) in memory
+				.iter()
+				.enumerate()
+				.take(segment.end)
+				.skip(segment.start)
+			{
+				write_labels(out, pointer)?;
+				writeln!(out, "{}:\t{}", pointer, word).map_err(could_not_write)?;
+			}
+			continue;
+		}
+		for (pointer, instruction) in Disassembler::at(&memory[..segment.end], segment.start) {
+			if leaders.contains(&pointer) {
+				if block > 0 {
+					writeln!(out).map_err(could_not_write)?;
+				}
+				writeln!(out, "# block {}", block).map_err(could_not_write)?;
+				block += 1;
+			}
+			write_labels(out, pointer)?;
+			let target = match instruction {
+				DecodedInstruction::Jmp(t)
+				| DecodedInstruction::Call(t)
+				| DecodedInstruction::Jt(_, t)
+				| DecodedInstruction::Jf(_, t) => names.get(&(t as usize)),
+				_ => None,
+			};
+			let text = instruction.to_string();
+			match target.and_then(|n| Some((text.rsplit_once('\t')?.0, n.first()?))) {
+				Some((code, name)) => writeln!(out, "{}:\t{}\t{}", pointer, code, name),
+				None => writeln!(out, "{}:\t{}", pointer, text),
+			}
+			.map_err(could_not_write)?;
+		}
+	}
+	Ok(())
+}
+
+/// Splits memory into code and data, where code is every instruction
+/// reachable from address 0 by following literal jumps and calls.
+pub fn classify(memory: &[u16]) -> Vec<Segment> {
+	let mut is_code = vec![false; memory.len()];
+	let mut visited = HashSet::new();
+	let mut pending = vec![0];
+	while let Some(pointer) = pending.pop() {
+		if !visited.insert(pointer) {
+			continue;
+		}
+		let (instruction, size) = match decode(memory, pointer) {
+			Some((DecodedInstruction::Unknown(_), _)) | None => continue,
+			Some(decoded) => decoded,
+		};
+		for c in &mut is_code[pointer..pointer + size] {
+			*c = true;
+		}
+		match instruction {
+			DecodedInstruction::Jmp(t) => pending.push(t as usize),
+			DecodedInstruction::Jt(_, t)
+			| DecodedInstruction::Jf(_, t)
+			| DecodedInstruction::Call(t) => {
+				pending.push(t as usize);
+				pending.push(pointer + size);
+			}
+			DecodedInstruction::Halt() | DecodedInstruction::Ret() => {}
+			_ => pending.push(pointer + size),
+		}
+	}
+
+	let mut segments: Vec<Segment> = Vec::new();
+	for (i, &code) in is_code.iter().enumerate() {
+		let kind = if code {
+			SegmentKind::Code
+		} else {
+			SegmentKind::Data
+		};
+		match segments.last_mut() {
+			Some(last) if last.kind == kind => last.end = i + 1,
+			_ => segments.push(Segment {
+				start: i,
+				end: i + 1,
+				kind,
+			}),
+		}
+	}
+	segments
+}
+
+/// The words that [`decompile`] writes as data and that aren't an opcode, a
+/// hint that the memory isn't all code.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UnknownSummary {
+	pub count: usize,
+	/// The address of the first unknown word.
+	pub first: Option<usize>,
+}
+
+impl fmt::Display for UnknownSummary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.first {
+			Some(first) => write!(
+				f,
+				"{} unknown words, the first at address {}.",
+				self.count, first
+			),
+			None => write!(f, "No unknown words."),
+		}
+	}
+}
+
+/// Counts the unknown words among those [`decompile`] writes as data.
+pub fn summarize_unknown(memory: &[u16]) -> UnknownSummary {
+	let mut summary = UnknownSummary::default();
+	for segment in classify(memory)
+		.into_iter()
+		.filter(|s| s.kind == SegmentKind::Data)
+	{
+		for (pointer, &word) in memory
+			.iter()
+			.enumerate()
+			.take(segment.end)
+			.skip(segment.start)
+		{
+			if opcode::mnemonic(word).is_none() {
+				summary.count += 1;
+				summary.first.get_or_insert(pointer);
+			}
+		}
+	}
+	summary
+}
+
+/// Finds the addresses that start a basic block. That is the first address,
+/// every literal jump or call target, and every instruction following a
+/// terminator.
+fn find_leaders(memory: &[u16]) -> HashSet<usize> {
+	let mut leaders = HashSet::new();
+	leaders.insert(0);
+	for (pointer, instruction) in Disassembler::new(memory) {
+		let target = match instruction {
+			DecodedInstruction::Jmp(t) | DecodedInstruction::Call(t) => Some(t),
+			DecodedInstruction::Jt(_, t) | DecodedInstruction::Jf(_, t) => Some(t),
+			_ => None,
+		};
+		if let Some(t) = target.filter(|&t| t < 32768) {
+			leaders.insert(t as usize);
+		}
+		if let DecodedInstruction::Halt()
+		| DecodedInstruction::Jmp(_)
+		| DecodedInstruction::Jt(_, _)
+		| DecodedInstruction::Jf(_, _)
+		| DecodedInstruction::Call(_)
+		| DecodedInstruction::Ret() = instruction
+		{
+			leaders.insert(pointer + instruction.size());
+		}
+	}
+	leaders.retain(|&l| l < memory.len());
+	leaders
+}
+
+/// Finds strings stored inline after a `call`, for subroutines that read
+/// their argument from the return address. A run of printable ASCII words
+/// right after a call counts if an instruction follows it. Returns an
+/// annotation like `inline string: "Hello"` keyed by the address of the
+/// string's first word.
+pub fn annotate_inline_strings(memory: &[u16]) -> HashMap<usize, String> {
+	let is_printable = |w: u16| (0x20..0x7F).contains(&w) || w == u16::from(b'\n');
+	let mut strings = HashMap::new();
 	let mut pointer = 0;
 	while pointer < memory.len() {
-		let handler = get_handler(memory[pointer]);
-		pointer += handler(memory, pointer, out)
-			.map_err(|e| format!("Could not write to output. {}", e))?;
+		let (instruction, size) =
+			decode(memory, pointer).unwrap_or((DecodedInstruction::Unknown(0), 1));
+		pointer += size;
+		if !matches!(instruction, DecodedInstruction::Call(t) if t < 32768) {
+			continue;
+		}
+		let start = pointer;
+		let text = memory[start..]
+			.iter()
+			.take_while(|&&w| is_printable(w))
+			.map(|&w| w as u8 as char)
+			.collect::<String>();
+		let end = start + text.len();
+		let followed_by_instruction = matches!(
+			decode(memory, end),
+			Some((instruction, _)) if !matches!(instruction, DecodedInstruction::Unknown(_))
+		);
+		if !text.is_empty() && followed_by_instruction {
+			strings.insert(start, format!("inline string: {:?}", text));
+			pointer = end;
+		}
 	}
-	Ok(())
+	strings
+}
+
+fn could_not_write(e: io::Error) -> String {
+	format!("Could not write to output. {}", e)
 }
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn count_unknown_words() {
+		// 0: noop, then 99 stops the decoding, and the halt is unreachable.
+		let memory = [21, 99, 0, 1234];
+		assert_eq!(summarize_unknown(&memory), UnknownSummary {
+			count: 2,
+			first: Some(1),
+		});
+		assert_eq!(
+			summarize_unknown(&memory).to_string(),
+			"2 unknown words, the first at address 1."
+		);
+		assert_eq!(summarize_unknown(&[21, 0]), UnknownSummary::default());
+	}
+
+	#[test]
+	fn aligned_columns() {
+		let memory = [4, 32768, 32769, 7, 21, 0, 65];
+		let mut output = Vec::new();
+		decompile_aligned(&memory, &mut output, 1).unwrap();
+		let expected = [
+			"0:    eq      32768 32769 7",
+			"4:    noop",
+			"5:    halt",
+			"6:    65",
+			"",
+		];
+		assert_eq!(
+			String::from_utf8(output),
+			Ok(expected.join("\n")),
+			"Every operand of eq should be on the same line."
+		);
+
+		let mut memory = vec![21; 10000];
+		memory.push(4);
+		memory.extend([32768, 32769, 7]);
+		let mut output = Vec::new();
+		decompile_aligned(&memory, &mut output, 1).unwrap();
+		assert_eq!(
+			String::from_utf8(output).unwrap().lines().nth(10000),
+			Some("10000: eq     32768 32769 7"),
+			"Wide columns should still be apart."
+		);
+
+		let mut output = Vec::new();
+		decompile_aligned(&[4, 32768, 32769, 7, 21], &mut output, 8).unwrap();
+		assert_eq!(
+			String::from_utf8(output),
+			Ok("0:      eq      32768 32769 7\n4:      noop\n".to_string()),
+			"Columns should line up with tab stops."
+		);
+	}
 
-fn get_handler<O: Write>(opcode: u16) -> Handler<O> {
-	match opcode {
-		0 => halt,
-		1 => set,
-		2 => push,
-		3 => pop,
-		4 => eq,
-		5 => gt,
-		6 => jmp,
-		7 => jt,
-		8 => jf,
-		9 => add,
-		10 => mult,
-		11 => mod_op,
-		12 => and,
-		13 => or,
-		14 => not,
-		15 => rmem,
-		16 => wmem,
-		17 => call,
-		18 => ret,
-		19 => out,
-		20 => in_op,
-		21 => noop,
-		_ => unknown,
-	}
-}
-
-fn halt<O: Write>(_: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\thalt", pointer)?;
-	Ok(1)
-}
-
-fn set<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tset\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn push<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tpush\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn pop<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tpop\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn eq<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\teq\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn gt<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tgt\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn jmp<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tjmp\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn jt<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tjt\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn jf<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tjf\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn add<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tadd\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn mult<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tmult\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn mod_op<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tmod\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn and<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tand\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn or<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tor\t{}\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2],
-		memory[pointer + 3]
-	)?;
-	Ok(4)
-}
-
-fn not<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\tnot\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn rmem<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\trmem\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn wmem<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(
-		out,
-		"{}:\twmem\t{}\t{}",
-		pointer,
-		memory[pointer + 1],
-		memory[pointer + 2]
-	)?;
-	Ok(3)
-}
-
-fn call<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tcall\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn ret<O: Write>(_: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tret", pointer)?;
-	Ok(1)
-}
-
-fn out<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tout\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn in_op<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tin\t{}", pointer, memory[pointer + 1])?;
-	Ok(2)
-}
-
-fn noop<O: Write>(_: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\tnoop", pointer)?;
-	Ok(1)
-}
-
-fn unknown<O: Write>(memory: &[u16], pointer: usize, out: &mut O) -> io::Result<usize> {
-	writeln!(out, "{}:\t{}", pointer, memory[pointer])?;
-	Ok(1)
+	#[test]
+	fn align_symbols_and_colors() {
+		let symbols = HashMap::from([("start".to_string(), 0)]);
+		let mut tabbed = Vec::new();
+		decompile_with_symbols(&[21, 0], &symbols, &mut tabbed).unwrap();
+		let mut output = Vec::new();
+		align_columns(&tabbed, 1, false, &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output),
+			Ok("start:\n0:    noop\n1:    halt\n".to_string())
+		);
+
+		let mut output = Vec::new();
+		align_columns(b"0:\tout\t65\n", 1, true, &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			format!(
+				"{}0:{}    {}out{}     {}65{}\n",
+				GRAY, RESET, CYAN, RESET, YELLOW, RESET
+			),
+			"Colors shouldn't count towards the widths."
+		);
+	}
+
+	#[test]
+	fn colored_columns() {
+		let memory = [9, 32768, 32769, 7, 0, 65];
+		let mut plain = Vec::new();
+		decompile(&memory, &mut plain).unwrap();
+		let mut output = Vec::new();
+		decompile_colored(&memory, &mut output, false).unwrap();
+		assert_eq!(output, plain, "Plain mode should match decompile.");
+		assert!(
+			!output.contains(&0x1b),
+			"Plain mode shouldn't have escape codes."
+		);
+
+		let mut output = Vec::new();
+		decompile_colored(&memory, &mut output, true).unwrap();
+		let paint = |text: &str, color: &str| format!("{}{}{}", color, text, RESET);
+		let expected = [
+			[
+				paint("0:", GRAY),
+				paint("add", CYAN),
+				paint("32768", GREEN),
+				paint("32769", GREEN),
+				paint("7", YELLOW),
+			]
+			.join("\t"),
+			[paint("4:", GRAY), paint("halt", CYAN)].join("\t"),
+			[paint("5:", GRAY), paint("65", YELLOW)].join("\t"),
+			String::new(),
+		];
+		assert_eq!(String::from_utf8(output), Ok(expected.join("\n")));
+	}
+
+	#[test]
+	fn blocks_around_branch() {
+		let mut output = Vec::new();
+		decompile_blocks(&[21, 6, 3, 0], &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output),
+			Ok("# block 0\n0:\tnoop\n1:\tjmp\t3\n\n# block 1\n3:\thalt\n".to_string()),
+			"The jump target should start a second block."
+		);
+	}
+
+	#[test]
+	fn classify_after_halt() {
+		assert_eq!(
+			classify(&[21, 0, 9, 1, 2, 3]),
+			vec![
+				Segment {
+					start: 0,
+					end: 2,
+					kind: SegmentKind::Code,
+				},
+				Segment {
+					start: 2,
+					end: 6,
+					kind: SegmentKind::Data,
+				},
+			],
+			"Words after a halt that nothing jumps to should be data."
+		);
+	}
+
+	#[test]
+	fn inline_strings_after_calls() {
+		// 0: call 11, "Hi!\n", call 11, "No", 22, 11: ret
+		let memory = [17, 11, 72, 105, 33, 10, 17, 11, 78, 111, 22, 18];
+		let strings = annotate_inline_strings(&memory);
+		assert_eq!(
+			strings.get(&2).map(String::as_str),
+			Some("inline string: \"Hi!\\n\""),
+			"The words after the first call should be a string."
+		);
+		assert_eq!(
+			strings.len(),
+			1,
+			"A run followed by something that isn't an instruction shouldn't count."
+		);
+
+		assert!(
+			annotate_inline_strings(&[17, 4, 19, 65, 18]).is_empty(),
+			"Calls followed by code have no strings."
+		);
+	}
+
+	#[test]
+	fn rust_constant_compiles() {
+		let mut output = Vec::new();
+		decompile_rust(&[19, 65, 0, 32768, 1, 2, 3, 4, 5], "PROGRAM", &mut output).unwrap();
+		let source = String::from_utf8(output).unwrap();
+		assert_eq!(
+			source,
+			[
+				"pub const PROGRAM: &[u16] = &[",
+				"\t0x0013, 0x0041, 0x0000, 0x8000, 0x0001, 0x0002, 0x0003, 0x0004,",
+				"\t0x0005,",
+				"];",
+				"",
+			]
+			.join("\n")
+		);
+
+		let dir = std::env::temp_dir().join("synacor_rust_constant_compiles");
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("program.rs");
+		std::fs::write(&path, source).unwrap();
+		let status = std::process::Command::new("rustc")
+			.args([
+				"--edition",
+				"2021",
+				"--crate-type",
+				"lib",
+				"--emit",
+				"metadata",
+			])
+			.arg("--out-dir")
+			.arg(&dir)
+			.arg(&path)
+			.status()
+			.unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(status.success(), "The constant should compile.");
+	}
+
+	#[test]
+	fn named_addresses() {
+		let mut symbols = HashMap::new();
+		symbols.insert("start".to_string(), 2);
+		let mut output = Vec::new();
+		decompile_with_symbols(&[21, 21, 19, 65, 6, 2], &symbols, &mut output).unwrap();
+		let expected = [
+			"0:\tnoop",
+			"1:\tnoop",
+			"start:",
+			"2:\tout\t65",
+			"4:\tjmp\tstart",
+			"",
+		];
+		assert_eq!(String::from_utf8(output), Ok(expected.join("\n")));
+	}
+
+	#[test]
+	fn decompile_data_after_halt() {
+		let mut output = Vec::new();
+		decompile(&[0, 9, 1], &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output),
+			Ok("0:\thalt\n1:\t9\n2:\t1\n".to_string()),
+			"Data should be written as plain words."
+		);
+	}
 }
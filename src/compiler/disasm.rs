@@ -0,0 +1,349 @@
+use std::fmt;
+
+use crate::opcode;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodedInstruction {
+	Halt(),
+	Set(u16, u16),
+	Push(u16),
+	Pop(u16),
+	Eq(u16, u16, u16),
+	Gt(u16, u16, u16),
+	Jmp(u16),
+	Jt(u16, u16),
+	Jf(u16, u16),
+	Add(u16, u16, u16),
+	Mult(u16, u16, u16),
+	Mod(u16, u16, u16),
+	And(u16, u16, u16),
+	Or(u16, u16, u16),
+	Not(u16, u16),
+	RMem(u16, u16),
+	WMem(u16, u16),
+	Call(u16),
+	Ret(),
+	Out(u16),
+	In(u16),
+	Noop(),
+	Unknown(u16),
+}
+
+/// Decodes the instruction at `pointer`, returning it together with its size
+/// in words. Returns `None` if `pointer` is outside of `memory` or if the
+/// instruction's operands would be.
+pub fn decode(memory: &[u16], pointer: usize) -> Option<(DecodedInstruction, usize)> {
+	let opcode = *memory.get(pointer)?;
+	let size = opcode::size(opcode).unwrap_or(1);
+	let a = memory.get(pointer + 1..pointer + size)?;
+	let instruction = match opcode {
+		0 => DecodedInstruction::Halt(),
+		1 => DecodedInstruction::Set(a[0], a[1]),
+		2 => DecodedInstruction::Push(a[0]),
+		3 => DecodedInstruction::Pop(a[0]),
+		4 => DecodedInstruction::Eq(a[0], a[1], a[2]),
+		5 => DecodedInstruction::Gt(a[0], a[1], a[2]),
+		6 => DecodedInstruction::Jmp(a[0]),
+		7 => DecodedInstruction::Jt(a[0], a[1]),
+		8 => DecodedInstruction::Jf(a[0], a[1]),
+		9 => DecodedInstruction::Add(a[0], a[1], a[2]),
+		10 => DecodedInstruction::Mult(a[0], a[1], a[2]),
+		11 => DecodedInstruction::Mod(a[0], a[1], a[2]),
+		12 => DecodedInstruction::And(a[0], a[1], a[2]),
+		13 => DecodedInstruction::Or(a[0], a[1], a[2]),
+		14 => DecodedInstruction::Not(a[0], a[1]),
+		15 => DecodedInstruction::RMem(a[0], a[1]),
+		16 => DecodedInstruction::WMem(a[0], a[1]),
+		17 => DecodedInstruction::Call(a[0]),
+		18 => DecodedInstruction::Ret(),
+		19 => DecodedInstruction::Out(a[0]),
+		20 => DecodedInstruction::In(a[0]),
+		21 => DecodedInstruction::Noop(),
+		_ => DecodedInstruction::Unknown(opcode),
+	};
+	Some((instruction, size))
+}
+
+impl DecodedInstruction {
+	pub fn opcode(&self) -> u16 {
+		match self {
+			DecodedInstruction::Halt() => 0,
+			DecodedInstruction::Set(_, _) => 1,
+			DecodedInstruction::Push(_) => 2,
+			DecodedInstruction::Pop(_) => 3,
+			DecodedInstruction::Eq(_, _, _) => 4,
+			DecodedInstruction::Gt(_, _, _) => 5,
+			DecodedInstruction::Jmp(_) => 6,
+			DecodedInstruction::Jt(_, _) => 7,
+			DecodedInstruction::Jf(_, _) => 8,
+			DecodedInstruction::Add(_, _, _) => 9,
+			DecodedInstruction::Mult(_, _, _) => 10,
+			DecodedInstruction::Mod(_, _, _) => 11,
+			DecodedInstruction::And(_, _, _) => 12,
+			DecodedInstruction::Or(_, _, _) => 13,
+			DecodedInstruction::Not(_, _) => 14,
+			DecodedInstruction::RMem(_, _) => 15,
+			DecodedInstruction::WMem(_, _) => 16,
+			DecodedInstruction::Call(_) => 17,
+			DecodedInstruction::Ret() => 18,
+			DecodedInstruction::Out(_) => 19,
+			DecodedInstruction::In(_) => 20,
+			DecodedInstruction::Noop() => 21,
+			DecodedInstruction::Unknown(opcode) => *opcode,
+		}
+	}
+
+	pub fn operands(&self) -> Vec<u16> {
+		match *self {
+			DecodedInstruction::Halt()
+			| DecodedInstruction::Ret()
+			| DecodedInstruction::Noop()
+			| DecodedInstruction::Unknown(_) => vec![],
+			DecodedInstruction::Push(a)
+			| DecodedInstruction::Pop(a)
+			| DecodedInstruction::Jmp(a)
+			| DecodedInstruction::Call(a)
+			| DecodedInstruction::Out(a)
+			| DecodedInstruction::In(a) => vec![a],
+			DecodedInstruction::Set(a, b)
+			| DecodedInstruction::Jt(a, b)
+			| DecodedInstruction::Jf(a, b)
+			| DecodedInstruction::Not(a, b)
+			| DecodedInstruction::RMem(a, b)
+			| DecodedInstruction::WMem(a, b) => vec![a, b],
+			DecodedInstruction::Eq(a, b, c)
+			| DecodedInstruction::Gt(a, b, c)
+			| DecodedInstruction::Add(a, b, c)
+			| DecodedInstruction::Mult(a, b, c)
+			| DecodedInstruction::Mod(a, b, c)
+			| DecodedInstruction::And(a, b, c)
+			| DecodedInstruction::Or(a, b, c) => vec![a, b, c],
+		}
+	}
+
+	/// The number of words this instruction occupies in memory.
+	pub fn size(&self) -> usize {
+		opcode::size(self.opcode()).unwrap_or(1)
+	}
+}
+
+impl fmt::Display for DecodedInstruction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match opcode::mnemonic(self.opcode())
+			.filter(|_| !matches!(self, DecodedInstruction::Unknown(_)))
+		{
+			Some(mnemonic) => {
+				write!(f, "{}", mnemonic)?;
+				for operand in self.operands() {
+					write!(f, "\t{}", operand)?;
+				}
+				Ok(())
+			}
+			None => write!(f, "{}", self.opcode()),
+		}
+	}
+}
+
+/// Checks that the instructions reached from address 0 by falling through
+/// decode cleanly. The walk stops at the first `halt`, `ret` or `jmp`, since
+/// what follows them might be data. Returns the first problem found.
+pub fn verify(memory: &[u16]) -> Result<(), String> {
+	let mut pointer = 0;
+	while let Some(&opcode) = memory.get(pointer) {
+		if opcode::mnemonic(opcode).is_none() {
+			return Err(format!("Unknown opcode {} at {}!", opcode, pointer));
+		}
+		let (instruction, size) = decode(memory, pointer).ok_or_else(|| {
+			format!(
+				"Instruction at {} reads past the end of memory at {}!",
+				pointer,
+				memory.len()
+			)
+		})?;
+		if let DecodedInstruction::Halt() | DecodedInstruction::Ret() | DecodedInstruction::Jmp(_) =
+			instruction
+		{
+			break;
+		}
+		pointer += size;
+	}
+	Ok(())
+}
+
+/// Iterates over a memory slice yielding each instruction with its address.
+/// Words that can't be decoded, because the instruction is cut short by the
+/// end of memory, are yielded as [`DecodedInstruction::Unknown`].
+pub struct Disassembler<'a> {
+	memory: &'a [u16],
+	pointer: usize,
+}
+
+impl<'a> Disassembler<'a> {
+	pub fn new(memory: &'a [u16]) -> Self {
+		Self::at(memory, 0)
+	}
+
+	/// Starts disassembling from `pointer` instead of the first address.
+	pub fn at(memory: &'a [u16], pointer: usize) -> Self {
+		Self {
+			memory,
+			pointer,
+		}
+	}
+}
+
+impl<'a> Iterator for Disassembler<'a> {
+	type Item = (usize, DecodedInstruction);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let address = self.pointer;
+		let (instruction, size) = decode(self.memory, address).or_else(|| {
+			self.memory
+				.get(address)
+				.map(|&w| (DecodedInstruction::Unknown(w), 1))
+		})?;
+		self.pointer += size;
+		Some((address, instruction))
+	}
+}
+
+/// Iterates over a memory slice yielding `(address, mnemonic, operands)` for
+/// each instruction, without decoding the operands. Words that aren't an
+/// instruction are yielded as `"data"` with the word itself as operand.
+pub struct InstructionIterator<'a> {
+	memory: &'a [u16],
+	pointer: usize,
+}
+
+pub fn iter_instructions(memory: &[u16]) -> InstructionIterator<'_> {
+	InstructionIterator {
+		memory,
+		pointer: 0,
+	}
+}
+
+impl<'a> Iterator for InstructionIterator<'a> {
+	type Item = (usize, &'static str, &'a [u16]);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let address = self.pointer;
+		let opcode = *self.memory.get(address)?;
+		let operands = opcode::size(opcode).and_then(|s| self.memory.get(address + 1..address + s));
+		Some(match (opcode::mnemonic(opcode), operands) {
+			(Some(mnemonic), Some(operands)) => {
+				self.pointer += 1 + operands.len();
+				(address, mnemonic, operands)
+			}
+			_ => {
+				self.pointer += 1;
+				(address, "data", &self.memory[address..=address])
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decode_each_opcode() {
+		let expected = [
+			(DecodedInstruction::Halt(), 1),
+			(DecodedInstruction::Set(1, 2), 3),
+			(DecodedInstruction::Push(1), 2),
+			(DecodedInstruction::Pop(1), 2),
+			(DecodedInstruction::Eq(1, 2, 3), 4),
+			(DecodedInstruction::Gt(1, 2, 3), 4),
+			(DecodedInstruction::Jmp(1), 2),
+			(DecodedInstruction::Jt(1, 2), 3),
+			(DecodedInstruction::Jf(1, 2), 3),
+			(DecodedInstruction::Add(1, 2, 3), 4),
+			(DecodedInstruction::Mult(1, 2, 3), 4),
+			(DecodedInstruction::Mod(1, 2, 3), 4),
+			(DecodedInstruction::And(1, 2, 3), 4),
+			(DecodedInstruction::Or(1, 2, 3), 4),
+			(DecodedInstruction::Not(1, 2), 3),
+			(DecodedInstruction::RMem(1, 2), 3),
+			(DecodedInstruction::WMem(1, 2), 3),
+			(DecodedInstruction::Call(1), 2),
+			(DecodedInstruction::Ret(), 1),
+			(DecodedInstruction::Out(1), 2),
+			(DecodedInstruction::In(1), 2),
+			(DecodedInstruction::Noop(), 1),
+			(DecodedInstruction::Unknown(22), 1),
+		];
+		for (opcode, e) in expected.iter().enumerate() {
+			assert_eq!(
+				decode(&[opcode as u16, 1, 2, 3], 0),
+				Some(*e),
+				"Decoding opcode {}.",
+				opcode
+			);
+		}
+	}
+
+	#[test]
+	fn decode_out_of_range() {
+		assert_eq!(decode(&[21], 1), None, "Decoding past the end.");
+		assert_eq!(decode(&[9, 1, 2], 0), None, "Decoding a cut short add.");
+	}
+
+	#[test]
+	fn disassembler_advances_by_size() {
+		let addresses = Disassembler::new(&[21, 19, 77, 9, 1, 2, 3, 0, 1, 2])
+			.map(|(a, _)| a)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			addresses,
+			vec![0, 1, 3, 7, 8, 9],
+			"Each address should follow the previous instruction, with the cut short set last."
+		);
+	}
+
+	#[test]
+	fn verify_truncated() {
+		assert_eq!(
+			verify(&[21, 19, 65, 0, 7]),
+			Ok(()),
+			"Data after halt is fine."
+		);
+		assert_eq!(
+			verify(&[21, 19, 65, 9, 32768, 1]),
+			Err("Instruction at 3 reads past the end of memory at 6!".to_string())
+		);
+		assert_eq!(
+			verify(&[21, 22, 0]),
+			Err("Unknown opcode 22 at 1!".to_string())
+		);
+	}
+
+	#[test]
+	fn display_cut_short_as_data() {
+		let lines = Disassembler::new(&[19, 65, 9, 1])
+			.map(|(_, i)| i.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			lines,
+			vec!["out\t65", "9", "1"],
+			"A cut short instruction should be shown as its number."
+		);
+	}
+
+	#[test]
+	fn iterate_instructions() {
+		let memory = [21, 19, 77, 9, 32768, 2, 3, 99, 1, 2];
+		assert_eq!(
+			iter_instructions(&memory).collect::<Vec<_>>(),
+			vec![
+				(0, "noop", &memory[1..1]),
+				(1, "out", &memory[2..3]),
+				(3, "add", &memory[4..7]),
+				(7, "data", &memory[7..8]),
+				(8, "data", &memory[8..9]),
+				(9, "data", &memory[9..10]),
+			],
+			"Unknown opcodes and cut short instructions should be data."
+		);
+	}
+}
@@ -0,0 +1,119 @@
+use std::{
+	io::{sink, Write},
+	str::FromStr,
+};
+
+use super::{compile, compile_with_listing, error::CompilerError, Parsing};
+
+/// What [`emit`] writes for a compiled program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+	/// The little endian binary the VM loads.
+	Binary,
+	/// One word per line as four hex digits.
+	Hex,
+	/// The listing of every source line with its address and bytes.
+	Annotated,
+}
+
+impl FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"binary" => Ok(OutputFormat::Binary),
+			"hex" => Ok(OutputFormat::Hex),
+			"annotated" => Ok(OutputFormat::Annotated),
+			_ => Err(format!("Unknown output format \"{}\"!", s)),
+		}
+	}
+}
+
+/// Compiles `parsing` and writes it to `out` in the given format.
+pub fn emit<O: Write>(
+	parsing: &Parsing,
+	format: OutputFormat,
+	out: &mut O,
+) -> Result<(), CompilerError> {
+	let could_not_write =
+		|e| CompilerError::Io(format!("Could not write to the output file. {}", e));
+	match format {
+		OutputFormat::Binary => compile(parsing, out).map_err(CompilerError::Compile),
+		OutputFormat::Hex => {
+			let mut binary = Vec::new();
+			compile(parsing, &mut binary).map_err(CompilerError::Compile)?;
+			for word in binary.chunks_exact(2) {
+				writeln!(out, "{:04X}", u16::from_le_bytes([word[0], word[1]]))
+					.map_err(could_not_write)?;
+			}
+			out.flush().map_err(could_not_write)
+		}
+		OutputFormat::Annotated => {
+			compile_with_listing(parsing, &mut sink(), out).map_err(CompilerError::Compile)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::parse;
+
+	const SOURCE: &str = "start: out 65\njmp start\n";
+
+	#[test]
+	fn hex_matches_binary() {
+		let parsing = parse(SOURCE.as_bytes()).unwrap();
+		let mut binary = Vec::new();
+		emit(&parsing, OutputFormat::Binary, &mut binary).unwrap();
+		let mut hex = Vec::new();
+		emit(&parsing, OutputFormat::Hex, &mut hex).unwrap();
+		let parsed = String::from_utf8(hex)
+			.unwrap()
+			.lines()
+			.flat_map(|l| u16::from_str_radix(l, 16).unwrap().to_le_bytes())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			parsed, binary,
+			"The hex words should parse back to the binary."
+		);
+	}
+
+	#[test]
+	fn annotated_has_mnemonics() {
+		let parsing = parse(SOURCE.as_bytes()).unwrap();
+		let mut out = Vec::new();
+		emit(&parsing, OutputFormat::Annotated, &mut out).unwrap();
+		let annotated = String::from_utf8(out).unwrap();
+		assert!(
+			annotated.contains("out 65") && annotated.contains("jmp start"),
+			"Every instruction should be annotated: {}",
+			annotated
+		);
+		assert!(annotated.starts_with("0000  13 00 41 00"));
+	}
+
+	/// A writer with no room left.
+	struct Full;
+
+	impl Write for Full {
+		fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+			Err(std::io::Error::other("disk full"))
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn write_error() {
+		let parsing = parse(SOURCE.as_bytes()).unwrap();
+		assert_eq!(
+			emit(&parsing, OutputFormat::Hex, &mut Full),
+			Err(CompilerError::Io(
+				"Could not write to the output file. disk full".to_string()
+			))
+		);
+	}
+}
@@ -0,0 +1,26 @@
+use std::fmt;
+
+/// What went wrong when writing a compiled program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompilerError {
+	/// The program couldn't be compiled, such as for a label that's never
+	/// defined.
+	Compile(String),
+	Io(String),
+}
+
+impl fmt::Display for CompilerError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CompilerError::Compile(message) | CompilerError::Io(message) => {
+				write!(f, "{}", message)
+			}
+		}
+	}
+}
+
+impl From<CompilerError> for String {
+	fn from(error: CompilerError) -> Self {
+		error.to_string()
+	}
+}
@@ -1,4 +1,39 @@
 mod compilation;
-mod decompilation;
-pub use compilation::{compile, parse, Parsing};
-pub use decompilation::decompile;
+pub mod decompilation;
+pub mod disasm;
+pub mod emit;
+pub mod error;
+pub mod symbols;
+#[cfg(test)]
+pub mod testing;
+pub use compilation::{
+	compile,
+	compile_optimized,
+	compile_with_endian,
+	compile_with_listing,
+	parse,
+	Endian,
+	Parsing,
+	UndefinedLabelError,
+	Warning,
+};
+pub use decompilation::{
+	align_columns,
+	annotate_inline_strings,
+	decompile,
+	decompile_aligned,
+	decompile_blocks,
+	decompile_colored,
+	decompile_rust,
+	decompile_with_symbols,
+	summarize_unknown,
+	UnknownSummary,
+};
+pub use disasm::{iter_instructions, verify, InstructionIterator};
+pub use emit::{emit, OutputFormat};
+
+/// Passes over a parsed program that change how it's listed, not what it
+/// compiles to.
+pub mod optimize {
+	pub use super::compilation::remove_noop_clusters;
+}
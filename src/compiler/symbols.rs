@@ -0,0 +1,110 @@
+use std::{
+	collections::HashMap,
+	io::{BufRead, BufReader, Read, Write},
+};
+
+use super::decompilation::{Segment, SegmentKind};
+
+/// Writes one `NAME = ADDR` line per label, in address order.
+pub fn write_symbols<'a, L, O>(labels: L, out: &mut O) -> Result<(), String>
+where
+	L: IntoIterator<Item = (&'a str, u16)>,
+	O: Write,
+{
+	let mut symbols = labels.into_iter().collect::<Vec<_>>();
+	symbols.sort_by(|(a_name, a_addr), (b_name, b_addr)| (a_addr, a_name).cmp(&(b_addr, b_name)));
+	for (name, addr) in symbols {
+		writeln!(out, "{} = {:04}", name, addr)
+			.map_err(|e| format!("Could not write to the symbols file. {}", e))?;
+	}
+	out.flush()
+		.map_err(|e| format!("Could not write to the symbols file. {}", e))
+}
+
+/// Writes one `KIND START..END` line per section, where `END` is the first
+/// address after it.
+pub fn write_sections<O: Write>(sections: &[Segment], out: &mut O) -> Result<(), String> {
+	for section in sections {
+		let kind = match section.kind {
+			SegmentKind::Code => "code",
+			SegmentKind::Data => "data",
+		};
+		writeln!(out, "{} {:04}..{:04}", kind, section.start, section.end)
+			.map_err(|e| format!("Could not write to the sections file. {}", e))?;
+	}
+	out.flush()
+		.map_err(|e| format!("Could not write to the sections file. {}", e))
+}
+
+pub fn read_symbols<I: Read>(input: I) -> Result<HashMap<String, u16>, String> {
+	let mut labels = HashMap::new();
+	for (i, line) in BufReader::new(input).lines().enumerate() {
+		let line = line.map_err(|_| format!("Error reading symbol on line {}!", i + 1))?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let (name, addr) = line
+			.split_once(" = ")
+			.and_then(|(n, a)| Some((n.trim(), a.trim().parse::<u16>().ok()?)))
+			.filter(|(n, _)| !n.is_empty())
+			.ok_or_else(|| format!("Malformed symbol on line {}:\n\t{}", i + 1, line))?;
+		labels.insert(name.to_string(), addr);
+	}
+	Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn write_and_read() {
+		let mut labels = HashMap::new();
+		labels.insert("main".to_string(), 0);
+		labels.insert("loop".to_string(), 42);
+		labels.insert("print".to_string(), 1337);
+
+		let mut out = Vec::new();
+		write_symbols(labels.iter().map(|(n, &a)| (n.as_str(), a)), &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out.clone()),
+			Ok("main = 0000\nloop = 0042\nprint = 1337\n".to_string()),
+			"Symbols should be written in address order."
+		);
+		assert_eq!(
+			read_symbols(out.as_slice()),
+			Ok(labels),
+			"Reading written symbols should give back the same labels."
+		);
+	}
+
+	#[test]
+	fn write_section_boundaries() {
+		let sections = [
+			Segment {
+				start: 0,
+				end: 12,
+				kind: SegmentKind::Code,
+			},
+			Segment {
+				start: 12,
+				end: 40,
+				kind: SegmentKind::Data,
+			},
+		];
+		let mut out = Vec::new();
+		write_sections(&sections, &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out),
+			Ok("code 0000..0012\ndata 0012..0040\n".to_string())
+		);
+	}
+
+	#[test]
+	fn read_malformed() {
+		assert_eq!(
+			read_symbols("main = 0\nloop 42\n".as_bytes()),
+			Err("Malformed symbol on line 2:\n\tloop 42".to_string()),
+		);
+	}
+}
@@ -0,0 +1,62 @@
+//! Helpers for testing the assembler and disassembler against each other.
+
+use quickcheck::{Arbitrary, Gen};
+
+use crate::opcode::OPCODES;
+
+/// Generates assembly source of `n_instructions` random instructions, each
+/// with the right number of random literal arguments.
+pub fn random_program(rng: &mut Gen, n_instructions: usize) -> String {
+	let mut source = String::new();
+	for _ in 0..n_instructions {
+		let (mnemonic, arg_count) = rng.choose(&OPCODES).unwrap();
+		source.push_str(mnemonic);
+		for _ in 0..*arg_count {
+			source.push_str(&format!(" {}", u16::arbitrary(rng)));
+		}
+		source.push('\n');
+	}
+	source
+}
+
+#[derive(Clone, Debug)]
+struct RandomProgram(String);
+
+impl Arbitrary for RandomProgram {
+	fn arbitrary(g: &mut Gen) -> Self {
+		let n_instructions = usize::arbitrary(g) % 64;
+		RandomProgram(random_program(g, n_instructions))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use quickcheck::QuickCheck;
+
+	use super::*;
+	use crate::compiler::{compile, decompile, parse};
+
+	fn assemble(source: &str) -> Vec<u8> {
+		let mut binary = Vec::new();
+		compile(&parse(source.as_bytes()).unwrap(), &mut binary).unwrap();
+		binary
+	}
+
+	fn round_trip(program: RandomProgram) -> bool {
+		let binary = assemble(&program.0);
+		let memory = binary
+			.chunks_exact(2)
+			.map(|c| u16::from_le_bytes([c[0], c[1]]))
+			.collect::<Vec<_>>();
+		let mut source = Vec::new();
+		decompile(&memory, &mut source).unwrap();
+		assemble(&String::from_utf8(source).unwrap()) == binary
+	}
+
+	#[test]
+	fn compile_decompile_round_trip() {
+		QuickCheck::new()
+			.tests(1000)
+			.quickcheck(round_trip as fn(RandomProgram) -> bool);
+	}
+}
@@ -0,0 +1,60 @@
+//! Entry points for the fuzz targets in `fuzz/`.
+
+use std::io::{self, Cursor};
+
+use crate::{
+	compiler::parse,
+	runtime::{data::Data, vm::VM},
+};
+
+const STEP_LIMIT: u64 = 10000;
+
+/// Runs arbitrary bytes as a binary. Any outcome but a panic is fine.
+pub fn fuzz_vm(data: &[u8]) {
+	let memory = data
+		.chunks_exact(2)
+		.map(|c| u16::from_le_bytes([c[0], c[1]]))
+		.collect::<Vec<_>>();
+	let mut vm = VM::new(Data::new(&memory));
+	let _ = vm.run_for_n_steps(STEP_LIMIT, &mut io::empty(), &mut io::sink());
+}
+
+/// Parses arbitrary bytes as assembly source. Any outcome but a panic is fine.
+pub fn fuzz_parser(data: &[u8]) {
+	let _ = parse(Cursor::new(data));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn to_bytes(memory: &[u16]) -> Vec<u8> {
+		memory.iter().flat_map(|w| w.to_le_bytes()).collect()
+	}
+
+	#[test]
+	fn invalid_opcode_number() {
+		fuzz_vm(&to_bytes(&[40000]));
+	}
+
+	#[test]
+	fn mod_by_zero() {
+		fuzz_vm(&to_bytes(&[11, 32768, 1, 0]));
+	}
+
+	#[test]
+	fn add_large_register_values() {
+		// A call at the top of memory pushes a return address above 15 bits,
+		// which is popped into r0 and added to itself.
+		let mut memory = vec![21; 32768];
+		memory[0..2].copy_from_slice(&[6, 32766]);
+		memory[100..107].copy_from_slice(&[3, 32768, 9, 32768, 32768, 32768, 0]);
+		memory[32766..32768].copy_from_slice(&[17, 100]);
+		fuzz_vm(&to_bytes(&memory));
+	}
+
+	#[test]
+	fn parser_extra_arguments() {
+		fuzz_parser(b"add 1 2 3 4 5\n");
+	}
+}
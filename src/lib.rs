@@ -0,0 +1,7 @@
+pub mod compiler;
+#[cfg(any(fuzzing, test))]
+pub mod fuzz;
+pub mod opcode;
+pub mod runtime;
+pub mod source_map;
+mod text;
@@ -1,42 +1,272 @@
-mod compiler;
-mod runtime;
-
 use std::{
+	borrow::Cow,
+	collections::HashSet,
+	convert::TryFrom,
 	fs,
-	io::{self, BufRead, Write},
+	io::{self, BufRead, IsTerminal, Read, Write},
+	thread,
 };
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use runtime::{data::Data, vm::VM};
+use synacor_challenge::{
+	compiler,
+	runtime::{
+		data::Data,
+		input::{self, InputInjector, RandomInput},
+		output::Tee,
+		vm::{BranchRecord, EofPolicy, ExecutionStats, ExitReason, VM},
+	},
+	source_map::SourceMap,
+};
 
 const COMMAND_EXECUTE: &str = "execute";
 const COMMAND_DECOMPILE: &str = "decompile";
 const COMMAND_COMPILE: &str = "compile";
+const COMMAND_VERIFY: &str = "verify";
+const COMMAND_HEXDUMP: &str = "hexdump";
+const COMMAND_INSPECT_SAVE: &str = "inspect-save";
+const COMMAND_DIFF_SAVE: &str = "diff-save";
+const COMMAND_REPLAY: &str = "replay";
+const COMMAND_REPL: &str = "repl";
 const ARG_BINARY: &str = "binary";
 const ARG_SOURCE: &str = "source";
 const ARG_LOAD: &str = "load";
+const ARG_SAVE: &str = "save";
+const ARG_SAVE_A: &str = "save_a";
+const ARG_SAVE_B: &str = "save_b";
+const ARG_TRANSCRIPT: &str = "transcript";
+const ARG_BLOCKS: &str = "blocks";
+const PARAM_SYMBOLS: &str = "symbols";
+const PARAM_LISTING: &str = "listing";
+const PARAM_SET_REGISTER: &str = "set-register";
+const PARAM_EOF_POLICY: &str = "eof-policy";
+const PARAM_PATCH: &str = "patch";
+const PARAM_PATCH_FILE: &str = "patch-file";
+const PARAM_NON_ASCII: &str = "non-ascii";
+const PARAM_LINE_ENDING: &str = "line-ending";
+const PARAM_NEWLINE: &str = "newline";
 const PARAM_OUT: &str = "out";
+const PARAM_OUT_FORMAT: &str = "out-format";
+const PARAM_ENDIAN: &str = "endian";
+const PARAM_TAB_WIDTH: &str = "tab-width";
+const PARAM_LOG: &str = "log";
+const PARAM_STATS: &str = "stats";
+const PARAM_NO_COLOR: &str = "no-color";
+const PARAM_DRY_RUN: &str = "dry-run";
+const PARAM_SOURCE_MAP: &str = "source-map";
+const PARAM_BUFFER_OUTPUT: &str = "buffer-output";
+const PARAM_RANGE: &str = "range";
+const PARAM_SECTIONS: &str = "sections";
+const PARAM_FOLLOW_JUMPS: &str = "follow-jumps";
+const PARAM_COVERAGE: &str = "coverage";
+const PARAM_MAX_STEPS: &str = "max-steps";
+const PARAM_FORMAT: &str = "format";
+const PARAM_DEBUG_SCRIPT: &str = "debug-script";
+const PARAM_MAX_OUTPUT: &str = "max-output";
+const PARAM_CHECK_JUMPS: &str = "check-jumps";
+const PARAM_ALLOW_DATA_JUMPS: &str = "allow-data-jumps";
+const PARAM_OPTIMIZE_NOOPS: &str = "optimize-noops";
+const PARAM_SUMMARY: &str = "summary";
+const PARAM_RANDOM_SEED: &str = "random-seed";
+const PARAM_CONST_NAME: &str = "const-name";
+const OUTPUT_BUFFER_SIZE: usize = 8192;
 
 fn main() {
 	let binary_arg = Arg::with_name(ARG_BINARY)
 		.required(true)
-		.help("A path to the binary you wish to operate on.");
+		.help("A path to the binary you wish to operate on, or - to read it from stdin.");
+	let endian_param = Arg::with_name(PARAM_ENDIAN)
+		.long("endian")
+		.short("e")
+		.takes_value(true)
+		.possible_values(&["little", "big"])
+		.default_value("little")
+		.help("The byte order of the words in the binary.");
 	let matches = App::new("Synacor Challenge Runtime")
 		.subcommand(
 			SubCommand::with_name(COMMAND_EXECUTE)
 				.arg(binary_arg.clone())
+				.arg(endian_param.clone())
 				.arg(
 					Arg::with_name(ARG_LOAD)
 						.long("load")
 						.short("l")
 						.takes_value(true)
 						.help("Start from this save file."),
+				)
+				.arg(
+					Arg::with_name(PARAM_SET_REGISTER)
+						.long("set-register")
+						.short("r")
+						.takes_value(true)
+						.multiple(true)
+						.number_of_values(1)
+						.help(
+							"Set a register before running, as <idx>=<value> where idx is 0-7. \
+							 May be repeated.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_EOF_POLICY)
+						.long("eof-policy")
+						.takes_value(true)
+						.possible_values(&["halt", "error", "block"])
+						.default_value("halt")
+						.help(
+							"What to do when the program reads past the end of the input. With \
+							 block, stdin is read on a separate thread that wakes the program.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_NON_ASCII)
+						.long("non-ascii")
+						.takes_value(true)
+						.possible_values(&["pass", "drop"])
+						.default_value("pass")
+						.help(
+							"Whether to pass non-ASCII input, like UTF-8 encoded accented \
+							 characters, to the program byte by byte or to drop it.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_LINE_ENDING)
+						.long("line-ending")
+						.takes_value(true)
+						.possible_values(&["crlf", "lf", "cr"])
+						.default_value("crlf")
+						.help(
+							"The line endings of the input, the program always reads LF. crlf \
+							 drops every CR, lf keeps them and cr reads them as LF.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_NEWLINE)
+						.long("newline")
+						.takes_value(true)
+						.possible_values(&["lf", "crlf", "raw"])
+						.conflicts_with(PARAM_LINE_ENDING)
+						.help(
+							"How to translate newlines, replacing --line-ending. lf reads CR LF \
+							 as LF, crlf writes LF as CR LF and raw passes everything on as is.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_OUT_FORMAT)
+						.long("out-format")
+						.takes_value(true)
+						.possible_values(&["char", "dec", "hex"])
+						.default_value("char")
+						.help(
+							"What out writes. char writes the character, dec and hex write its \
+							 code as a number, separated by spaces.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_PATCH)
+						.long("patch")
+						.short("p")
+						.takes_value(true)
+						.multiple(true)
+						.number_of_values(1)
+						.help(
+							"Overwrite a word of memory before running, as <addr>=<value>. May be \
+							 repeated, patches are applied in order.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_RANDOM_SEED)
+						.long("random-seed")
+						.takes_value(true)
+						.help(
+							"Read pseudo-random bytes from this seed instead of stdin, the same \
+							 ones for every run with the same seed. For fuzzing input handling.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_PATCH_FILE)
+						.long("patch-file")
+						.takes_value(true)
+						.help(
+							"A file of patches, one <addr>=<value> per line in hex (0x...) or \
+							 decimal. Blank lines and lines starting with # are skipped. Applied \
+							 before any --patch.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_LOG)
+						.long("log")
+						.takes_value(true)
+						.help("Also write the output of the program to this file."),
+				)
+				.arg(
+					Arg::with_name(PARAM_STATS)
+						.long("stats")
+						.help("Print how much the program did once it stops."),
+				)
+				.arg(
+					Arg::with_name(PARAM_FOLLOW_JUMPS)
+						.long("follow-jumps")
+						.takes_value(true)
+						.help(
+							"A path where to write whether each jt and jf jumped, once the \
+							 program stops. Any existing file will be overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_MAX_STEPS)
+						.long("max-steps")
+						.short("n")
+						.takes_value(true)
+						.help("Stop after this many steps, counted from where the run starts."),
+				)
+				.arg(
+					Arg::with_name(PARAM_MAX_OUTPUT)
+						.long("max-output")
+						.takes_value(true)
+						.help("Stop with an error once the program has written this many bytes."),
+				)
+				.arg(
+					Arg::with_name(PARAM_COVERAGE)
+						.long("coverage")
+						.takes_value(true)
+						.help(
+							"A path where to write the address of every instruction executed, \
+							 once the program stops. Any existing file will be overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_BUFFER_OUTPUT)
+						.long("buffer-output")
+						.help(
+							"Write the output a few kilobytes at a time, and before reading \
+							 input, instead of a line at a time.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_SOURCE_MAP)
+						.long("source-map")
+						.takes_value(true)
+						.help(
+							"A source map made when compiling the binary, to name the source line \
+							 in error messages.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_DEBUG_SCRIPT)
+						.long("debug-script")
+						.takes_value(true)
+						.conflicts_with(ARG_LOAD)
+						.help(
+							"A path to a file of debugger commands to run from the start of the \
+							 program, printing what they show once it's done.",
+						),
 				),
 		)
 		.subcommand(
 			SubCommand::with_name(COMMAND_DECOMPILE)
 				.about("Writes the binary in human readable text.")
 				.arg(binary_arg.clone())
+				.arg(endian_param.clone())
 				.arg(
 					Arg::with_name(PARAM_OUT)
 						.long("out")
@@ -47,7 +277,60 @@ fn main() {
 							 overwritten. If not specified, the output will be written to the \
 							 terminal.",
 						),
-				),
+				)
+				.arg(
+					Arg::with_name(ARG_BLOCKS)
+						.long("blocks")
+						.short("b")
+						.help("Mark where each basic block begins."),
+				)
+				.arg(
+					Arg::with_name(PARAM_TAB_WIDTH)
+						.long("tab-width")
+						.takes_value(true)
+						.default_value("1")
+						.help(
+							"Align the columns with spaces, to tab stops this many columns apart. \
+							 1 keeps the address and mnemonic columns 6 and 8 wide, and 0 \
+							 separates the columns with tabs instead.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_NO_COLOR)
+						.long("no-color")
+						.help("Don't color the output, even when writing to a terminal."),
+				)
+				.arg(
+					Arg::with_name(PARAM_FORMAT)
+						.long("format")
+						.short("f")
+						.takes_value(true)
+						.possible_values(&["text", "rust-const"])
+						.default_value("text")
+						.help("Write assembly text, or a Rust constant with every word."),
+				)
+				.arg(
+					Arg::with_name(PARAM_SYMBOLS)
+						.long("symbols")
+						.short("s")
+						.takes_value(true)
+						.conflicts_with(ARG_BLOCKS)
+						.help(
+							"A symbol table written when compiling the binary, to name the \
+							 labeled addresses and the jumps to them.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_CONST_NAME)
+						.long("const-name")
+						.takes_value(true)
+						.default_value("BINARY")
+						.help("The name of the constant written by --format rust-const."),
+				)
+				.arg(Arg::with_name(PARAM_SUMMARY).long("summary").help(
+					"Print how many words written as data aren't an opcode, and where the first \
+					 one is, to stderr.",
+				)),
 		)
 		.subcommand(
 			SubCommand::with_name(COMMAND_COMPILE)
@@ -57,10 +340,156 @@ fn main() {
 						.required(true)
 						.help("A path to the file you wish to compile."),
 				)
-				.arg(Arg::with_name(PARAM_OUT).required(true).help(
-					"A path where to write the output, any existing file will be overwritten.",
-				)),
+				.arg(endian_param.clone())
+				.arg(
+					Arg::with_name(PARAM_OUT)
+						.required_unless(PARAM_DRY_RUN)
+						.help(
+							"A path where to write the output, or - to write it to stdout. Any \
+							 existing file will be overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_FORMAT)
+						.long("format")
+						.short("f")
+						.takes_value(true)
+						.possible_values(&["binary", "hex", "annotated"])
+						.default_value("binary")
+						.help(
+							"How to write the output: the binary, one hex word per line, or an \
+							 annotated listing.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_CHECK_JUMPS)
+						.long("check-jumps")
+						.takes_value(true)
+						.possible_values(&["warn", "error"])
+						.help(
+							"Check that every jump and call goes to the start of an instruction, \
+							 and either warn or fail when one doesn't.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_ALLOW_DATA_JUMPS)
+						.long("allow-data-jumps")
+						.requires(PARAM_CHECK_JUMPS)
+						.help("Let --check-jumps accept jumps anywhere into data."),
+				)
+				.arg(
+					Arg::with_name(PARAM_OPTIMIZE_NOOPS)
+						.long("optimize-noops")
+						.help(
+							"List runs of noops as one block of padding in the listing and the \
+							 annotated format. Every other output is the same either way.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_DRY_RUN)
+						.long("dry-run")
+						.help("Print the size of the binary instead of writing any files."),
+				)
+				.arg(
+					Arg::with_name(PARAM_SYMBOLS)
+						.long("symbols")
+						.short("s")
+						.takes_value(true)
+						.help(
+							"A path where to write the symbol table, any existing file will be \
+							 overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_LISTING)
+						.long("listing")
+						.takes_value(true)
+						.help(
+							"A path where to write a listing of every source line with its \
+							 address and bytes, any existing file will be overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_SOURCE_MAP)
+						.long("source-map")
+						.takes_value(true)
+						.help(
+							"A path where to write a JSON map from every address to its source \
+							 line, any existing file will be overwritten.",
+						),
+				)
+				.arg(
+					Arg::with_name(PARAM_SECTIONS)
+						.long("sections")
+						.takes_value(true)
+						.help(
+							"A path where to write where each .section ended up, any existing \
+							 file will be overwritten.",
+						),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name(COMMAND_VERIFY)
+				.about("Checks that the binary decodes cleanly from the first address.")
+				.arg(binary_arg.clone())
+				.arg(endian_param.clone()),
+		)
+		.subcommand(
+			SubCommand::with_name(COMMAND_HEXDUMP)
+				.about("Prints the words of the binary in hexadecimal, eight to a line.")
+				.arg(binary_arg.clone())
+				.arg(endian_param.clone())
+				.arg(
+					Arg::with_name(PARAM_RANGE)
+						.long("range")
+						.takes_value(true)
+						.help("Only print the addresses from start up to end, as <start>:<end>."),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name(COMMAND_INSPECT_SAVE)
+				.about("Prints the pointer, registers and stack of a save file.")
+				.arg(binary_arg.clone())
+				.arg(endian_param.clone())
+				.arg(
+					Arg::with_name(ARG_SAVE)
+						.required(true)
+						.help("A path to the save file, made from the binary."),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name(COMMAND_REPLAY)
+				.about(
+					"Runs the binary with a transcript as its only input, halting when the \
+					 transcript ends.",
+				)
+				.arg(binary_arg.clone())
+				.arg(endian_param.clone())
+				.arg(
+					Arg::with_name(ARG_TRANSCRIPT)
+						.required(true)
+						.help("A path to the input to replay."),
+				),
 		)
+		.subcommand(
+			SubCommand::with_name(COMMAND_DIFF_SAVE)
+				.about("Prints where the states of two save files differ.")
+				.arg(binary_arg)
+				.arg(endian_param)
+				.arg(
+					Arg::with_name(ARG_SAVE_A)
+						.required(true)
+						.help("A path to the first save file, made from the binary."),
+				)
+				.arg(
+					Arg::with_name(ARG_SAVE_B)
+						.required(true)
+						.help("A path to the second save file, made from the binary."),
+				),
+		)
+		.subcommand(SubCommand::with_name(COMMAND_REPL).about(
+			"Runs instructions as they're typed, one line at a time, on a VM with empty memory.",
+		))
 		.setting(AppSettings::SubcommandRequired)
 		.get_matches();
 
@@ -68,6 +497,12 @@ fn main() {
 		(COMMAND_EXECUTE, Some(m)) => execute(m),
 		(COMMAND_DECOMPILE, Some(m)) => decompile(m),
 		(COMMAND_COMPILE, Some(m)) => compile(m),
+		(COMMAND_VERIFY, Some(m)) => verify(m),
+		(COMMAND_HEXDUMP, Some(m)) => hexdump(m),
+		(COMMAND_INSPECT_SAVE, Some(m)) => inspect_save(m),
+		(COMMAND_DIFF_SAVE, Some(m)) => diff_save(m),
+		(COMMAND_REPLAY, Some(m)) => replay(m),
+		(COMMAND_REPL, Some(_)) => run_repl(io::stdin().lock(), &mut io::stdout()),
 		_ => Err("No subcommand provided!".to_string()),
 	};
 
@@ -77,53 +512,332 @@ fn main() {
 }
 
 fn load_binary(args: &ArgMatches) -> Result<Vec<u16>, String> {
-	fs::read(args.value_of(ARG_BINARY).unwrap())
-		.map(|f| {
-			f.chunks_exact(2)
-				.map(|c| u16::from_le_bytes([c[0], c[1]]))
-				.collect()
-		})
-		.map_err(|e| format!("Error when loading binary file. {}", e))
+	load_binary_path(args.value_of(ARG_BINARY).unwrap(), endian(args)?)
+}
+
+fn endian(args: &ArgMatches) -> Result<compiler::Endian, String> {
+	args.value_of(PARAM_ENDIAN).unwrap().parse()
+}
+
+/// Loads a binary from a path, where `-` means stdin.
+fn load_binary_path(path: &str, endian: compiler::Endian) -> Result<Vec<u16>, String> {
+	if path == "-" {
+		read_binary(io::stdin(), endian)
+	} else {
+		fs::File::open(path)
+			.map_err(|e| format!("Error when loading binary file. {}", e))
+			.and_then(|f| read_binary(f, endian))
+	}
+}
+
+fn read_binary<R: Read>(mut reader: R, endian: compiler::Endian) -> Result<Vec<u16>, String> {
+	let mut bytes = Vec::new();
+	reader
+		.read_to_end(&mut bytes)
+		.map_err(|e| format!("Error when loading binary file. {}", e))?;
+	if bytes.len() % 2 != 0 {
+		return Err("Binary has an odd byte length; it may be truncated".to_string());
+	}
+	Ok(bytes
+		.chunks_exact(2)
+		.map(|c| endian.from_bytes([c[0], c[1]]))
+		.collect())
 }
 
 fn execute(args: &ArgMatches) -> Result<(), String> {
 	let memory = load_binary(args)?;
+	if let Some(script_path) = args.value_of(PARAM_DEBUG_SCRIPT) {
+		let commands = fs::read_to_string(script_path)
+			.map_err(|e| format!("Error when reading debug script. {}", e))?;
+		print!(
+			"{}",
+			VM::run_script(&memory, &commands, io::stdin(), io::stdout())?
+		);
+		return Ok(());
+	}
 	let mut vm = if let Some(load_path) = args.value_of(ARG_LOAD) {
-		fs::read(load_path)
-			.map(|f| VM::load(&memory, &f))
-			.map_err(|e| format!("Error when loading save file. {}", e))??
+		load_save(&memory, load_path)?
 	} else {
 		VM::new(Data::new(&memory))
 	};
+	if let Some(assignments) = args.values_of(PARAM_SET_REGISTER) {
+		set_registers(&mut vm.data, assignments)?;
+	}
+	if let Some(patch_path) = args.value_of(PARAM_PATCH_FILE) {
+		let patch_file = fs::File::open(patch_path)
+			.map_err(|e| format!("Error when opening patch file. {}", e))?;
+		patch_memory_from_file(&mut vm.data, io::BufReader::new(patch_file))?;
+	}
+	if let Some(patches) = args.values_of(PARAM_PATCH) {
+		patch_memory(&mut vm.data, patches)?;
+	}
+	let eof_policy = args.value_of(PARAM_EOF_POLICY).unwrap().parse()?;
+	let non_ascii_policy = args.value_of(PARAM_NON_ASCII).unwrap().parse()?;
+	let mut vm = vm
+		.with_eof_policy(eof_policy)
+		.with_non_ascii_policy(non_ascii_policy)
+		.with_line_ending(args.value_of(PARAM_LINE_ENDING).unwrap().parse()?)
+		.with_out_format(args.value_of(PARAM_OUT_FORMAT).unwrap().parse()?);
+	if let Some(mode) = args.value_of(PARAM_NEWLINE) {
+		vm = vm.with_newline_mode(mode.parse()?);
+	}
+	if let Some(map_path) = args.value_of(PARAM_SOURCE_MAP) {
+		let map_file = fs::File::open(map_path)
+			.map_err(|e| format!("Error when opening source map file. {}", e))?;
+		vm = vm.with_source_map(SourceMap::read(map_file)?);
+	}
+	if let Some(max_steps) = args.value_of(PARAM_MAX_STEPS) {
+		let max_steps = max_steps
+			.parse()
+			.map_err(|_| format!("Could not parse step limit \"{}\".", max_steps))?;
+		vm = vm.with_max_steps(max_steps);
+	}
+	if let Some(max_output) = args.value_of(PARAM_MAX_OUTPUT) {
+		let max_output = max_output
+			.parse()
+			.map_err(|_| format!("Could not parse output limit \"{}\".", max_output))?;
+		vm = vm.with_max_output(max_output);
+	}
+	if args.is_present(PARAM_BUFFER_OUTPUT) {
+		vm = vm.with_buffered_output(OUTPUT_BUFFER_SIZE);
+	}
+	if args.is_present(PARAM_STATS) {
+		vm.enable_stats();
+	}
+	if args.is_present(PARAM_FOLLOW_JUMPS) {
+		vm.enable_branch_trace();
+	}
+	if args.is_present(PARAM_COVERAGE) {
+		vm.enable_coverage();
+	}
 
-	vm.run(&mut io::stdin(), &mut io::stdout())?;
+	let mut output: Box<dyn Write> = if let Some(log_path) = args.value_of(PARAM_LOG) {
+		let log =
+			fs::File::create(log_path).map_err(|e| format!("Error when creating log. {}", e))?;
+		Box::new(Tee::new(io::stdout(), io::BufWriter::new(log)))
+	} else {
+		Box::new(io::stdout())
+	};
+	// With the block policy stdin is read on a thread of its own, so the save
+	// prompt has to read its answer through the same injector.
+	let mut stdin_injector = None;
+	let reason = if let Some(seed) = args.value_of(PARAM_RANDOM_SEED) {
+		let seed = seed
+			.parse()
+			.map_err(|_| format!("Could not parse random seed \"{}\".", seed))?;
+		vm.run(&mut RandomInput::new(seed), &mut output)
+	} else if eof_policy == EofPolicy::Block {
+		let (blocking_vm, injector) = vm.with_injector();
+		vm = blocking_vm;
+		send_stdin(input::stdin(non_ascii_policy)?, injector.clone());
+		stdin_injector = Some(injector);
+		vm.run(&mut io::empty(), &mut output)
+	} else {
+		vm.run_stdio_to(&mut output)
+	}?;
+	// Writes the rest of the log before waiting on the save prompt.
+	drop(output);
+	if let Some(message) = exit_message(reason) {
+		println!("{}", message);
+	}
+	if args.is_present(PARAM_STATS) {
+		write_stats(&vm.statistics(), &mut io::stdout())?;
+	}
+	if let Some(trace_path) = args.value_of(PARAM_FOLLOW_JUMPS) {
+		let mut trace_file = fs::File::create(trace_path)
+			.map_err(|e| format!("Error when opening branch trace file. {}", e))?;
+		write_branch_trace(vm.branch_trace(), &mut trace_file)?;
+	}
+	if let (Some(coverage_path), Some(coverage)) = (args.value_of(PARAM_COVERAGE), vm.coverage()) {
+		let mut coverage_file = fs::File::create(coverage_path)
+			.map_err(|e| format!("Error when opening coverage file. {}", e))?;
+		write_coverage(coverage, &mut coverage_file)?;
+	}
 
 	print!("Save state to file (leave blank to discard): ");
 	io::stdout()
 		.flush()
 		.map_err(|e| format!("Could not read line. {}", e))?;
-	if let Some(save_path) = io::stdin()
-		.lock()
+	let answers: Box<dyn BufRead> = match stdin_injector {
+		Some(injector) => Box::new(io::BufReader::new(injector)),
+		None => Box::new(io::stdin().lock()),
+	};
+	if let Some(save_path) = answers
 		.lines()
 		.next()
 		.transpose()
 		.map_err(|e| format!("Could not read line. {}", e))?
-		.filter(|l| l.len() > 0)
+		.filter(|l| !l.is_empty())
 	{
-		fs::write(save_path, vm.save()?).map_err(|e| format!("Error when saving state. {}", e))?;
+		let mut file =
+			fs::File::create(save_path).map_err(|e| format!("Error when saving state. {}", e))?;
+		vm.save_to_writer(&mut file)?;
+	}
+
+	Ok(())
+}
+
+/// Sends `stdin` through `injector` a line at a time, from a thread of its
+/// own, so a VM blocked at the end of its input wakes up when a line is typed.
+/// The injector is closed when stdin ends.
+fn send_stdin(stdin: Box<dyn Read + Send>, injector: InputInjector) {
+	thread::spawn(move || {
+		let mut stdin = io::BufReader::new(stdin);
+		let mut line = Vec::new();
+		while let Ok(1..) = stdin.read_until(b'\n', &mut line) {
+			injector.send(&line);
+			line.clear();
+		}
+		injector.close();
+	});
+}
+
+/// What to tell the user about why the program stopped, if it's unusual.
+fn exit_message(reason: ExitReason) -> Option<String> {
+	match reason {
+		ExitReason::StepLimit(limit) => {
+			Some(format!("Execution halted: step limit {} reached.", limit))
+		}
+		ExitReason::Halt | ExitReason::Interrupt => None,
 	}
+}
 
+fn write_stats<O: Write>(stats: &ExecutionStats, out: &mut O) -> Result<(), String> {
+	writeln!(
+		out,
+		"Instructions executed: {}",
+		stats.instructions_executed
+	)
+	.and_then(|_| writeln!(out, "Memory reads: {}", stats.memory_reads))
+	.and_then(|_| writeln!(out, "Memory writes: {}", stats.memory_writes))
+	.and_then(|_| writeln!(out, "Max stack depth: {}", stats.stack_max_depth))
+	.and_then(|_| writeln!(out, "Elapsed: {:.3?}", stats.elapsed))
+	.map_err(|e| format!("Could not write statistics. {}", e))
+}
+
+/// Writes one `<addr> taken` or `<addr> not taken` line per branch.
+fn write_branch_trace<O: Write>(trace: &[BranchRecord], out: &mut O) -> Result<(), String> {
+	for record in trace {
+		let taken = if record.taken { "taken" } else { "not taken" };
+		writeln!(out, "{} {}", record.addr, taken)
+			.map_err(|e| format!("Could not write branch trace. {}", e))?;
+	}
 	Ok(())
 }
 
+/// Writes the covered addresses one per line, in order.
+fn write_coverage<O: Write>(coverage: &HashSet<usize>, out: &mut O) -> Result<(), String> {
+	let mut addresses = coverage.iter().collect::<Vec<_>>();
+	addresses.sort_unstable();
+	for address in addresses {
+		writeln!(out, "{}", address).map_err(|e| format!("Could not write coverage. {}", e))?;
+	}
+	Ok(())
+}
+
+fn load_save<'a>(memory: &'a [u16], path: &str) -> Result<VM<'a>, String> {
+	let mut file =
+		fs::File::open(path).map_err(|e| format!("Error when loading save file. {}", e))?;
+	Ok(VM::load_from_reader(memory, &mut file)?)
+}
+
+fn set_registers<'a>(
+	data: &mut Data,
+	assignments: impl IntoIterator<Item = &'a str>,
+) -> Result<(), String> {
+	for assignment in assignments {
+		let (index, value) = parse_assignment(assignment)?;
+		data.set_register(index as usize, value)?;
+	}
+	Ok(())
+}
+
+fn patch_memory<'a>(
+	data: &mut Data,
+	patches: impl IntoIterator<Item = &'a str>,
+) -> Result<(), String> {
+	for patch in patches {
+		let (address, value) = parse_assignment(patch)?;
+		data.write_memory(address, value)?;
+	}
+	Ok(())
+}
+
+/// Applies the `<addr>=<value>` lines of a patch file, in hex or decimal,
+/// skipping blank lines and `#` comments.
+fn patch_memory_from_file<R: BufRead>(data: &mut Data, patches: R) -> Result<(), String> {
+	for (i, line) in patches.lines().enumerate() {
+		let line = line.map_err(|e| format!("Could not read patch file. {}", e))?;
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		line.split_once('=')
+			.and_then(|(a, v)| Some((parse_patch_number(a)?, parse_patch_number(v)?)))
+			.ok_or_else(|| format!("Could not parse \"{}\", expected <addr>=<value>.", line))
+			.and_then(|(address, value)| Ok(data.write_memory(address, value)?))
+			.map_err(|e| format!("Error on line {} of the patch file.\n\t{}", i + 1, e))?;
+	}
+	Ok(())
+}
+
+/// Parses a number that's either decimal or hex starting with `0x`.
+fn parse_patch_number(number: &str) -> Option<u16> {
+	let number = number.trim();
+	match number.strip_prefix("0x") {
+		Some(hex) => u16::from_str_radix(hex, 16).ok(),
+		None => number.parse().ok(),
+	}
+}
+
+/// Parses a `<a>=<b>` command line value.
+fn parse_assignment(assignment: &str) -> Result<(u16, u16), String> {
+	assignment
+		.split_once('=')
+		.and_then(|(a, b)| Some((a.trim().parse().ok()?, b.trim().parse().ok()?)))
+		.ok_or_else(|| {
+			format!(
+				"Could not parse \"{}\", expected two numbers as <a>=<b>.",
+				assignment
+			)
+		})
+}
+
 fn decompile(args: &ArgMatches) -> Result<(), String> {
 	let memory = load_binary(args)?;
-	match args.value_of(PARAM_OUT) {
-		Some(out_path) => match fs::File::create(out_path) {
-			Ok(mut o) => compiler::decompile(&memory, &mut o),
-			Err(e) => Err(format!("Error when opening out file. {}", e)),
-		},
-		None => compiler::decompile(&memory, &mut io::stdout()),
+	let use_color = args.value_of(PARAM_OUT).is_none()
+		&& !args.is_present(PARAM_NO_COLOR)
+		&& io::stdout().is_terminal();
+	let mut out: Box<dyn Write> = match args.value_of(PARAM_OUT) {
+		Some(out_path) => Box::new(
+			fs::File::create(out_path)
+				.map_err(|e| format!("Error when opening out file. {}", e))?,
+		),
+		None => Box::new(io::stdout()),
+	};
+	if args.is_present(PARAM_SUMMARY) {
+		eprintln!("{}", compiler::summarize_unknown(&memory));
+	}
+	if args.value_of(PARAM_FORMAT) == Some("rust-const") {
+		let const_name = args.value_of(PARAM_CONST_NAME).unwrap();
+		compiler::decompile_rust(&memory, const_name, &mut out)
+	} else {
+		let tab_width = args.value_of(PARAM_TAB_WIDTH).unwrap();
+		let tab_width = tab_width
+			.parse()
+			.map_err(|_| format!("Could not parse tab width \"{}\".", tab_width))?;
+		let mut tabbed = Vec::new();
+		if let Some(symbols_path) = args.value_of(PARAM_SYMBOLS) {
+			let symbols_file = fs::File::open(symbols_path)
+				.map_err(|e| format!("Error when opening symbols file. {}", e))?;
+			let symbols = compiler::symbols::read_symbols(symbols_file)?;
+			compiler::decompile_with_symbols(&memory, &symbols, &mut tabbed)?;
+		} else if args.is_present(ARG_BLOCKS) {
+			compiler::decompile_blocks(&memory, &mut tabbed)?;
+		} else {
+			compiler::decompile(&memory, &mut tabbed)?;
+		}
+		compiler::align_columns(&tabbed, tab_width, use_color, &mut out)
 	}
 }
 
@@ -131,7 +845,615 @@ fn compile(args: &ArgMatches) -> Result<(), String> {
 	let source = fs::File::open(args.value_of(ARG_SOURCE).unwrap())
 		.map_err(|e| format!("Error when opening source file. {}", e))?;
 	let parsing = compiler::parse(source)?;
-	let mut file = fs::File::create(args.value_of(PARAM_OUT).unwrap())
-		.map_err(|e| format!("Error when opening out file. {}", e))?;
-	compiler::compile(&parsing, &mut file)
+	for warning in parsing.warnings() {
+		eprintln!("Warning: {}", warning);
+	}
+	for warning in parsing.lint() {
+		eprintln!("Warning: {}", warning);
+	}
+	if let Some(check) = args.value_of(PARAM_CHECK_JUMPS) {
+		let warnings = parsing.check_jump_targets(args.is_present(PARAM_ALLOW_DATA_JUMPS));
+		if check == "error" && !warnings.is_empty() {
+			return Err(warnings
+				.iter()
+				.map(ToString::to_string)
+				.collect::<Vec<_>>()
+				.join("\n"));
+		}
+		for warning in warnings {
+			eprintln!("Warning: {}", warning);
+		}
+	}
+	// Folded noops are only for reading, the binary, symbols, sections and
+	// source map are all made from the parsing as is.
+	let listed = if args.is_present(PARAM_OPTIMIZE_NOOPS) {
+		Cow::Owned(fold_noops(&parsing))
+	} else {
+		Cow::Borrowed(&parsing)
+	};
+	if args.is_present(PARAM_DRY_RUN) {
+		let size = parsing.byte_size();
+		println!("{} bytes, {} words", size, size / 2);
+		return Ok(());
+	}
+	let mut out: Box<dyn Write> = match args.value_of(PARAM_OUT).unwrap() {
+		"-" => Box::new(io::stdout()),
+		out_path => Box::new(
+			fs::File::create(out_path)
+				.map_err(|e| format!("Error when opening out file. {}", e))?,
+		),
+	};
+	let format = args.value_of(PARAM_FORMAT).unwrap().parse()?;
+	let program = match format {
+		compiler::OutputFormat::Annotated => &*listed,
+		_ => &parsing,
+	};
+	write_program(program, format, endian(args)?, &mut out)?;
+	if let Some(symbols_path) = args.value_of(PARAM_SYMBOLS) {
+		let mut symbols_file = fs::File::create(symbols_path)
+			.map_err(|e| format!("Error when opening symbols file. {}", e))?;
+		compiler::symbols::write_symbols(parsing.labels(), &mut symbols_file)?;
+	}
+	if let Some(listing_path) = args.value_of(PARAM_LISTING) {
+		let mut listing_file = fs::File::create(listing_path)
+			.map_err(|e| format!("Error when opening listing file. {}", e))?;
+		compiler::compile_with_listing(&listed, &mut io::sink(), &mut listing_file)?;
+	}
+	if let Some(sections_path) = args.value_of(PARAM_SECTIONS) {
+		let mut sections_file = fs::File::create(sections_path)
+			.map_err(|e| format!("Error when opening sections file. {}", e))?;
+		compiler::symbols::write_sections(parsing.sections(), &mut sections_file)?;
+	}
+	if let Some(map_path) = args.value_of(PARAM_SOURCE_MAP) {
+		let mut map_file = fs::File::create(map_path)
+			.map_err(|e| format!("Error when opening source map file. {}", e))?;
+		SourceMap::from_parsing(&parsing, args.value_of(ARG_SOURCE).unwrap())
+			.write(&mut map_file)?;
+	}
+	Ok(())
+}
+
+/// A copy of `parsing` with its runs of noops folded into padding blocks.
+fn fold_noops(parsing: &compiler::Parsing) -> compiler::Parsing {
+	let mut folded = parsing.clone();
+	compiler::optimize::remove_noop_clusters(&mut folded, 2);
+	folded
+}
+
+fn write_program<O: Write>(
+	parsing: &compiler::Parsing,
+	format: compiler::OutputFormat,
+	endian: compiler::Endian,
+	out: &mut O,
+) -> Result<(), String> {
+	match format {
+		compiler::OutputFormat::Binary => compiler::compile_with_endian(parsing, out, endian),
+		format => Ok(compiler::emit(parsing, format, out)?),
+	}
+}
+
+fn verify(args: &ArgMatches) -> Result<(), String> {
+	compiler::verify(&load_binary(args)?)?;
+	println!("OK");
+	Ok(())
+}
+
+fn hexdump(args: &ArgMatches) -> Result<(), String> {
+	let memory = load_binary(args)?;
+	let (start, end) = match args.value_of(PARAM_RANGE) {
+		Some(range) => parse_range(range)?,
+		None => (0, memory.len()),
+	};
+	let end = end.min(memory.len());
+	write_hexdump(&memory[start.min(end)..end], start, &mut io::stdout())
+}
+
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+	range
+		.split_once(':')
+		.and_then(|(a, b)| Some((a.trim().parse().ok()?, b.trim().parse().ok()?)))
+		.filter(|(start, end)| start <= end)
+		.ok_or_else(|| {
+			format!(
+				"Could not parse range \"{}\", expected <start>:<end> with start not after end.",
+				range
+			)
+		})
+}
+
+/// Writes `words` eight to a line as `<addr>: XXXX XXXX ...`, followed by the
+/// printable low bytes as ASCII. `start` is the address of the first word.
+fn write_hexdump<O: Write>(words: &[u16], start: usize, out: &mut O) -> Result<(), String> {
+	for (i, line) in words.chunks(8).enumerate() {
+		let hex = line
+			.iter()
+			.map(|w| format!("{:04X}", w))
+			.collect::<Vec<_>>()
+			.join(" ");
+		let ascii = line
+			.iter()
+			.map(|&w| match (w & 0xFF) as u8 {
+				b @ 0x20..=0x7E => b as char,
+				_ => '.',
+			})
+			.collect::<String>();
+		writeln!(out, "{:04X}: {:39}  |{}|", start + i * 8, hex, ascii)
+			.map_err(|e| format!("Could not write hexdump. {}", e))?;
+	}
+	Ok(())
+}
+
+fn inspect_save(args: &ArgMatches) -> Result<(), String> {
+	let memory = load_binary(args)?;
+	let vm = load_save(&memory, args.value_of(ARG_SAVE).unwrap())?;
+	write_state(&vm, &mut io::stdout())
+}
+
+fn write_state<O: Write>(vm: &VM, out: &mut O) -> Result<(), String> {
+	let registers = vm
+		.data
+		.registers()
+		.iter()
+		.enumerate()
+		.map(|(i, r)| format!("r{}={}", i, r))
+		.collect::<Vec<_>>();
+	let stack = vm
+		.data
+		.stack()
+		.iter()
+		.map(u16::to_string)
+		.collect::<Vec<_>>();
+	writeln!(out, "Pointer: {}", vm.pointer)
+		.and_then(|_| writeln!(out, "Registers: {}", registers.join(" ")))
+		.and_then(|_| writeln!(out, "Stack (top last): {}", stack.join(" ")))
+		.and_then(|_| writeln!(out, "Memory changes: {}", vm.data.memory_changes().len()))
+		.map_err(|e| format!("Could not write state. {}", e))
+}
+
+fn replay(args: &ArgMatches) -> Result<(), String> {
+	let memory = load_binary(args)?;
+	let path = args.value_of(ARG_TRANSCRIPT).unwrap();
+	let transcript = fs::File::open(path)
+		.map_err(|e| format!("Error when opening transcript \"{}\". {}", path, e))?;
+	replay_transcript(&memory, io::BufReader::new(transcript), &mut io::stdout())?;
+	Ok(())
+}
+
+/// Runs `memory` from the start with `transcript` as its input, halting
+/// when it's exhausted so that the run is the same every time.
+fn replay_transcript<R: Read, O: Write>(
+	memory: &[u16],
+	mut transcript: R,
+	output: &mut O,
+) -> Result<ExitReason, String> {
+	VM::new(Data::new(memory))
+		.with_eof_policy(EofPolicy::Halt)
+		.run(&mut transcript, output)
+}
+
+/// Assembles each line of `input` into memory at the pointer and runs it,
+/// until `input` ends. The program gets no input of its own, so `in` halts.
+fn run_repl<R: BufRead, O: Write>(input: R, output: &mut O) -> Result<(), String> {
+	let memory = vec![0; Data::full_address_space()];
+	let mut vm = VM::new(Data::new(&memory));
+	let could_not_write = |e: io::Error| format!("Could not write to output. {}", e);
+	write!(output, "> ")
+		.and_then(|_| output.flush())
+		.map_err(could_not_write)?;
+	for line in input.lines() {
+		let line = line.map_err(|e| format!("Could not read line. {}", e))?;
+		match run_repl_line(&mut vm, &line, output) {
+			Ok(true) => {}
+			Ok(false) => writeln!(output, "Halted.").map_err(could_not_write)?,
+			Err(e) => writeln!(output, "Error: {}", e).map_err(could_not_write)?,
+		}
+		write!(output, "> ")
+			.and_then(|_| output.flush())
+			.map_err(could_not_write)?;
+	}
+	Ok(())
+}
+
+/// Assembles `line` at the pointer and runs it, returning whether the
+/// program is still running.
+fn run_repl_line<O: Write>(vm: &mut VM, line: &str, output: &mut O) -> Result<bool, String> {
+	// Assembled at the pointer, so that labels and jumps land where the code
+	// is written. Errors name the line as typed, not the one after the `.org`.
+	let source = format!(".org {}\n{}", vm.pointer, line);
+	let as_typed = |e: String| e.replacen("line 2", "line 1", 1);
+	let parsing = compiler::parse(source.as_bytes()).map_err(as_typed)?;
+	let mut binary = Vec::new();
+	compiler::compile(&parsing, &mut binary).map_err(as_typed)?;
+	let words = binary
+		.chunks_exact(2)
+		.map(|word| u16::from_le_bytes([word[0], word[1]]))
+		.collect::<Vec<_>>();
+	if words.len() <= vm.pointer {
+		return Ok(true);
+	}
+	for (address, &word) in words.iter().enumerate().skip(vm.pointer) {
+		let address = u16::try_from(address)
+			.map_err(|_| "The instruction doesn't fit in memory!".to_string())?;
+		vm.data.write_memory(address, word)?;
+	}
+	// Pseudo-ops like sub are several instructions, so it runs until the
+	// pointer leaves what was just written.
+	let written = vm.pointer..words.len();
+	loop {
+		if vm.step(&mut io::empty(), output)?.halted {
+			return Ok(false);
+		}
+		if !written.contains(&vm.pointer) {
+			return Ok(true);
+		}
+	}
+}
+
+fn diff_save(args: &ArgMatches) -> Result<(), String> {
+	let memory = load_binary(args)?;
+	let a = load_save(&memory, args.value_of(ARG_SAVE_A).unwrap())?;
+	let b = load_save(&memory, args.value_of(ARG_SAVE_B).unwrap())?;
+	write_diff(&memory, &a, &b, &mut io::stdout())
+}
+
+/// Writes one line for each difference between the states of `a` and `b`,
+/// as "what: a != b".
+fn write_diff<O: Write>(memory: &[u16], a: &VM, b: &VM, out: &mut O) -> Result<(), String> {
+	let mut lines = Vec::new();
+	if a.pointer != b.pointer {
+		lines.push(format!("Pointer: {} != {}", a.pointer, b.pointer));
+	}
+	let (registers_a, registers_b) = (a.data.registers(), b.data.registers());
+	for (i, (ra, rb)) in registers_a.iter().zip(registers_b.iter()).enumerate() {
+		if ra != rb {
+			lines.push(format!("r{}: {} != {}", i, ra, rb));
+		}
+	}
+	let (stack_a, stack_b) = (a.data.stack(), b.data.stack());
+	if stack_a.len() != stack_b.len() {
+		lines.push(format!(
+			"Stack length: {} != {}",
+			stack_a.len(),
+			stack_b.len()
+		));
+	}
+	for (i, (sa, sb)) in stack_a.iter().zip(stack_b.iter()).enumerate() {
+		if sa != sb {
+			lines.push(format!("Stack {}: {} != {}", i, sa, sb));
+		}
+	}
+	let mut addresses = a
+		.data
+		.memory_changes()
+		.keys()
+		.chain(b.data.memory_changes().keys())
+		.copied()
+		.collect::<Vec<_>>();
+	addresses.sort_unstable();
+	addresses.dedup();
+	for address in addresses {
+		let value = |vm: &VM| match vm.data.memory_changes().get(&address) {
+			Some(&value) => value,
+			None => memory.get(address).copied().unwrap_or(0),
+		};
+		let (ma, mb) = (value(a), value(b));
+		if ma != mb {
+			lines.push(format!("Memory {}: {} != {}", address, ma, mb));
+		}
+	}
+	if lines.is_empty() {
+		lines.push("No differences.".to_string());
+	}
+	for line in lines {
+		writeln!(out, "{}", line).map_err(|e| format!("Could not write diff. {}", e))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn binary_from_reader_and_file() {
+		let bytes = [21, 0, 19, 0, 77, 0, 0, 0];
+		let path = std::env::temp_dir().join("synacor_binary_from_reader_and_file.bin");
+		fs::write(&path, bytes).unwrap();
+		let from_file = load_binary_path(path.to_str().unwrap(), compiler::Endian::Little);
+		fs::remove_file(&path).unwrap();
+		assert_eq!(
+			read_binary(&bytes[..], compiler::Endian::Little),
+			Ok(vec![21, 19, 77, 0])
+		);
+		assert_eq!(read_binary(&bytes[..], compiler::Endian::Little), from_file);
+	}
+
+	#[test]
+	fn binary_odd_length() {
+		assert_eq!(
+			read_binary(&[21, 0, 0][..], compiler::Endian::Little),
+			Err("Binary has an odd byte length; it may be truncated".to_string())
+		);
+		assert_eq!(
+			read_binary(&[21, 0, 0, 0][..], compiler::Endian::Little),
+			Ok(vec![21, 0])
+		);
+	}
+
+	#[test]
+	fn endian_round_trip() {
+		let parsing = compiler::parse("set 32768 1\nhalt\n".as_bytes()).unwrap();
+		for &endian in &[compiler::Endian::Little, compiler::Endian::Big] {
+			let mut binary = Vec::new();
+			compiler::compile_with_endian(&parsing, &mut binary, endian).unwrap();
+			assert_eq!(
+				read_binary(binary.as_slice(), endian),
+				Ok(vec![1, 32768, 1, 0]),
+				"Compiling and loading {:?} endian.",
+				endian
+			);
+		}
+
+		let mut binary = Vec::new();
+		compiler::compile_with_endian(&parsing, &mut binary, compiler::Endian::Big).unwrap();
+		assert_eq!(
+			&binary[..4],
+			&[0, 1, 128, 0],
+			"Big endian should put the high byte first."
+		);
+	}
+
+	#[test]
+	fn program_to_buffer() {
+		let parsing = compiler::parse("out 65\nhalt\n".as_bytes()).unwrap();
+		let mut out = Vec::new();
+		write_program(
+			&parsing,
+			compiler::OutputFormat::Binary,
+			compiler::Endian::Little,
+			&mut out,
+		)
+		.unwrap();
+		assert_eq!(out, vec![19, 0, 65, 0, 0, 0]);
+	}
+
+	#[test]
+	fn fold_noops_keeps_source_map() {
+		let source = ["out 65", "noop", "noop", "noop", "halt"].join("\n");
+		let parsing = compiler::parse(source.as_bytes()).unwrap();
+		let source_map = SourceMap::from_parsing(&parsing, "noops.asm");
+		let folded = fold_noops(&parsing);
+		assert_eq!(SourceMap::from_parsing(&parsing, "noops.asm"), source_map);
+		assert_eq!(source_map.location(3).map(|l| l.line), Some(3));
+		assert_eq!(
+			SourceMap::from_parsing(&folded, "noops.asm").location(3),
+			None,
+			"Only the copy is folded."
+		);
+	}
+
+	#[test]
+	fn step_limit_message() {
+		let memory = [6, 0];
+		let mut vm = VM::new(Data::new(&memory)).with_max_steps(100);
+		let reason = vm.run(&mut io::empty(), &mut io::sink()).unwrap();
+		assert_eq!(
+			exit_message(reason),
+			Some("Execution halted: step limit 100 reached.".to_string())
+		);
+		assert_eq!(exit_message(ExitReason::Halt), None);
+	}
+
+	#[test]
+	fn hexdump_lines() {
+		let words = [72, 105, 0, 0x1234, 19, 65, 32768, 10, 126];
+		let mut output = Vec::new();
+		write_hexdump(&words, 16, &mut output).unwrap();
+		let expected = [
+			"0010: 0048 0069 0000 1234 0013 0041 8000 000A  |Hi.4.A..|",
+			"0018: 007E                                     |~|",
+			"",
+		];
+		assert_eq!(String::from_utf8(output), Ok(expected.join("\n")));
+		assert_eq!(parse_range("2:10"), Ok((2, 10)));
+		assert!(parse_range("10:2").is_err(), "Start after end.");
+	}
+
+	#[test]
+	fn inspect_saved_state() {
+		let memory = [21, 21, 0];
+		let mut vm = VM::new(Data::new(&memory));
+		vm.pointer = 2;
+		vm.data.set_register(3, 1234).unwrap();
+		vm.data.push_stack(7);
+		vm.data.write_memory(100, 1).unwrap();
+		let path = std::env::temp_dir().join("synacor_inspect_saved_state.sav");
+		vm.save_to_writer(&mut fs::File::create(&path).unwrap())
+			.unwrap();
+		let loaded = load_save(&memory, path.to_str().unwrap());
+		fs::remove_file(&path).unwrap();
+
+		let mut output = Vec::new();
+		write_state(&loaded.unwrap(), &mut output).unwrap();
+		let expected = [
+			"Pointer: 2",
+			"Registers: r0=0 r1=0 r2=0 r3=1234 r4=0 r5=0 r6=0 r7=0",
+			"Stack (top last): 7",
+			"Memory changes: 1",
+			"",
+		];
+		assert_eq!(String::from_utf8(output), Ok(expected.join("\n")));
+	}
+
+	#[test]
+	fn replay_until_exhausted() {
+		// 0: in r0, out r0, jmp 0
+		let memory = [20, 32768, 19, 32768, 6, 0];
+		let mut output = Vec::new();
+		assert_eq!(
+			replay_transcript(&memory, &b"look\n"[..], &mut output),
+			Ok(ExitReason::Halt)
+		);
+		assert_eq!(
+			output, b"look\n",
+			"Every byte of the transcript should be read."
+		);
+	}
+
+	#[test]
+	fn repl_runs_each_line() {
+		let mut output = Vec::new();
+		run_repl(&b"out 72\nnoot\nhalt\n"[..], &mut output).unwrap();
+		let output = String::from_utf8(output).unwrap();
+		assert!(output.starts_with("> H> Error: "), "{}", output);
+		assert!(
+			output.ends_with("> Halted.\n> "),
+			"The REPL should keep going after an error: {}",
+			output
+		);
+	}
+
+	#[test]
+	fn repl_labels_at_pointer() {
+		let mut output = Vec::new();
+		// Reads the rmem opcode 15 at the label, not the out at 0, so 15 + 50
+		// is an A.
+		let lines = [
+			"out 66",
+			"here: rmem 32768 here",
+			"add 32768 32768 50",
+			"out 32768",
+		];
+		run_repl(lines.join("\n").as_bytes(), &mut output).unwrap();
+		assert_eq!(String::from_utf8(output).unwrap(), "> B> > > A> ");
+	}
+
+	#[test]
+	fn repl_runs_all_of_sub() {
+		let mut output = Vec::new();
+		let lines = [
+			"set 32768 5",
+			"sub 32768 32768 2",
+			"add 32768 32768 48",
+			"out 32768",
+			"",
+		];
+		run_repl(lines.join("\n").as_bytes(), &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			"> > > > 3> ",
+			"Every instruction sub expands to should run."
+		);
+	}
+
+	#[test]
+	fn diff_saved_states() {
+		let memory = [21, 21, 0];
+		let mut a = VM::new(Data::new(&memory));
+		a.data.set_register(2, 5).unwrap();
+		a.data.write_memory(1, 0).unwrap();
+		a.data.write_memory(100, 1).unwrap();
+		let mut b = a.clone();
+		b.data.set_register(2, 6).unwrap();
+		b.data.push_stack(3);
+		b.data.write_memory(100, 2).unwrap();
+		b.data.write_memory(0, 21).unwrap();
+		let path = std::env::temp_dir().join("synacor_diff_saved_states.sav");
+		b.save_to_writer(&mut fs::File::create(&path).unwrap())
+			.unwrap();
+		let loaded = load_save(&memory, path.to_str().unwrap());
+		fs::remove_file(&path).unwrap();
+
+		let mut output = Vec::new();
+		write_diff(&memory, &a, &loaded.unwrap(), &mut output).unwrap();
+		let expected = [
+			"r2: 5 != 6",
+			"Stack length: 0 != 1",
+			"Memory 100: 1 != 2",
+			"",
+		];
+		assert_eq!(String::from_utf8(output), Ok(expected.join("\n")));
+
+		let mut output = Vec::new();
+		write_diff(&memory, &a, &a, &mut output).unwrap();
+		assert_eq!(
+			String::from_utf8(output),
+			Ok("No differences.\n".to_string())
+		);
+	}
+
+	#[test]
+	fn set_register_seven() {
+		let memory = [0];
+		let mut data = Data::new(&memory);
+		set_registers(&mut data, vec!["7=25734"]).unwrap();
+		assert_eq!(data.get_register(7), Ok(25734));
+	}
+
+	#[test]
+	fn patch_to_noop() {
+		let memory = [0, 0];
+		let mut vm = VM::new(Data::new(&memory));
+		patch_memory(&mut vm.data, vec!["0=21"]).unwrap();
+		assert_eq!(
+			vm.step(&mut io::empty(), &mut io::sink())
+				.map(|info| info.mnemonic),
+			Ok("noop"),
+			"The first instruction should be a noop."
+		);
+		assert_eq!(vm.pointer, 1);
+	}
+
+	#[test]
+	fn patch_invalid() {
+		let memory = [0, 0];
+		let mut data = Data::new(&memory);
+		assert_eq!(
+			patch_memory(&mut data, vec!["0=21", "32768=21"]),
+			Err("Writing to out of range address 32768!".to_string())
+		);
+		assert_eq!(
+			patch_memory(&mut data, vec!["0=noop"]),
+			Err("Could not parse \"0=noop\", expected two numbers as <a>=<b>.".to_string())
+		);
+	}
+
+	#[test]
+	fn patch_from_file() {
+		let path = std::env::temp_dir().join("synacor_patch_from_file.txt");
+		fs::write(&path, "# Skip the check.\n0x1=21\n\n2 = 0x15\n").unwrap();
+		let patch_file = fs::File::open(&path).unwrap();
+		let memory = [0, 0, 0];
+		let mut data = Data::new(&memory);
+		let result = patch_memory_from_file(&mut data, io::BufReader::new(patch_file));
+		fs::remove_file(&path).unwrap();
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			(data.get_number(1), data.get_number(2)),
+			(Ok(21), Ok(21)),
+			"Both lines should be applied."
+		);
+		assert_eq!(
+			patch_memory_from_file(&mut data, &b"0=1\n0=x\n"[..]),
+			Err(
+				"Error on line 2 of the patch file.\n\tCould not parse \"0=x\", expected \
+				 <addr>=<value>."
+					.to_string()
+			)
+		);
+	}
+
+	#[test]
+	fn set_register_invalid() {
+		let memory = [0];
+		let mut data = Data::new(&memory);
+		assert_eq!(
+			set_registers(&mut data, vec!["8=1"]),
+			Err("Register 8 is out of range 0-7!".to_string())
+		);
+		assert_eq!(
+			set_registers(&mut data, vec!["7"]),
+			Err("Could not parse \"7\", expected two numbers as <a>=<b>.".to_string())
+		);
+	}
 }
@@ -0,0 +1,88 @@
+/// Mnemonic and argument count of every opcode, indexed by opcode.
+pub const OPCODES: [(&str, usize); 22] = [
+	("halt", 0),
+	("set", 2),
+	("push", 1),
+	("pop", 1),
+	("eq", 3),
+	("gt", 3),
+	("jmp", 1),
+	("jt", 2),
+	("jf", 2),
+	("add", 3),
+	("mult", 3),
+	("mod", 3),
+	("and", 3),
+	("or", 3),
+	("not", 2),
+	("rmem", 2),
+	("wmem", 2),
+	("call", 1),
+	("ret", 0),
+	("out", 1),
+	("in", 1),
+	("noop", 0),
+];
+
+pub fn mnemonic(opcode: u16) -> Option<&'static str> {
+	OPCODES.get(opcode as usize).map(|(m, _)| *m)
+}
+
+pub fn arg_count(opcode: u16) -> Option<usize> {
+	OPCODES.get(opcode as usize).map(|(_, c)| *c)
+}
+
+/// The number of words an instruction with this opcode occupies, the opcode
+/// itself included.
+pub fn size(opcode: u16) -> Option<usize> {
+	arg_count(opcode).map(|c| c + 1)
+}
+
+pub fn from_mnemonic(mnemonic: &str) -> Option<u16> {
+	OPCODES
+		.iter()
+		.position(|(m, _)| *m == mnemonic)
+		.map(|o| o as u16)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::{compile, disasm::decode, parse};
+
+	#[test]
+	fn consistent_arg_counts() {
+		for opcode in 0..OPCODES.len() as u16 {
+			let (mnemonic, arg_count) = OPCODES[opcode as usize];
+			assert_eq!(from_mnemonic(mnemonic), Some(opcode));
+
+			let mut memory = vec![opcode];
+			memory.extend((1..=arg_count as u16).map(|a| a + 32767));
+			assert_eq!(
+				decode(&memory, 0).map(|(_, s)| s),
+				Some(arg_count + 1),
+				"The decoder should agree on the size of {}.",
+				mnemonic
+			);
+			assert!(
+				decode(&memory[..arg_count], 0).is_none(),
+				"The decoder should need {} arguments for {}.",
+				arg_count,
+				mnemonic
+			);
+
+			let args = (1..=arg_count).map(|a| format!(" {}", a + 32767));
+			let source = format!("{}{}\nnoop\n", mnemonic, args.collect::<String>());
+			let mut binary = Vec::new();
+			compile(&parse(source.as_bytes()).unwrap(), &mut binary).unwrap();
+			assert_eq!(
+				binary.len(),
+				(arg_count + 2) * 2,
+				"The assembler should agree on the size of {}.",
+				mnemonic
+			);
+			assert_eq!(&binary[..2], &opcode.to_le_bytes());
+			assert_eq!(&binary[binary.len() - 2..], &[21, 0]);
+		}
+	}
+}
@@ -1,14 +1,47 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use serde::{Deserialize, Serialize};
+use super::error::RuntimeError;
 
-#[derive(Clone, Deserialize, Serialize)]
+/// Addresses are 15 bits. Memory past the end of the binary is free to use,
+/// and reads as 0 until written.
+const ADDRESS_SPACE: usize = 32768;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+	Read,
+	Write,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryAccess {
+	pub addr: usize,
+	pub kind: AccessKind,
+	pub value: u16,
+	pub instruction_pointer: usize,
+}
+
+/// Called with the index and new value of a register when it's written.
+/// Shared between clones of [`Data`].
+type RegisterCallback = Rc<RefCell<dyn FnMut(usize, u16)>>;
+
+enum Operand {
+	Literal(u16),
+	Register(usize),
+}
+
+#[derive(Clone)]
 pub struct Data<'a> {
-	#[serde(skip)]
 	pub(super) memory: &'a [u16],
-	memory_changes: HashMap<usize, u16>,
-	registers: [u16; 8],
-	stack: Vec<u16>,
+	pub(super) memory_changes: HashMap<usize, u16>,
+	pub(super) registers: [u16; 8],
+	pub(super) stack: Vec<u16>,
+	/// How many times `read_memory` and `write_memory` have succeeded.
+	pub(super) memory_reads: u64,
+	pub(super) memory_writes: u64,
+	access_log: Option<Vec<MemoryAccess>>,
+	current_ip: usize,
+	expanded_length: usize,
+	register_callbacks: [Option<RegisterCallback>; 8],
 }
 
 impl<'a> Data<'a> {
@@ -18,27 +51,137 @@ impl<'a> Data<'a> {
 			memory_changes: HashMap::new(),
 			registers: [0; 8],
 			stack: Vec::new(),
+			memory_reads: 0,
+			memory_writes: 0,
+			access_log: None,
+			current_ip: 0,
+			expanded_length: 0,
+			register_callbacks: Default::default(),
 		}
 	}
 
-	pub fn get_number(&self, i: usize) -> Result<u16, String> {
-		let value = self.read_memory(i as u16)?;
-		if value > 32775 {
-			Err(format!("Number at {} ({}) is too large!", i, value))
-		} else if value > 32767 {
-			Ok(self.registers[value as usize - 32768])
-		} else {
-			Ok(value)
+	/// Like [`Data::new`], but with the registers set to `registers`.
+	pub fn with_registers(memory: &'a [u16], registers: [u16; 8]) -> Self {
+		Self::from_state(memory, registers, Vec::new(), HashMap::new())
+	}
+
+	/// Rebuilds a state from its registers, stack and the words written over
+	/// `memory`, keyed by address.
+	pub fn from_state(
+		memory: &'a [u16],
+		registers: [u16; 8],
+		stack: Vec<u16>,
+		changes: HashMap<usize, u16>,
+	) -> Self {
+		Self {
+			memory_changes: changes,
+			registers,
+			stack,
+			..Self::new(memory)
 		}
 	}
 
-	pub fn set_number(&mut self, r: usize, value: u16) -> Result<(), String> {
-		let register = self.read_memory(r as u16)? as usize;
-		if 32767 < register && register < 32776 {
-			self.registers[register - 32768] = value;
-			Ok(())
-		} else {
-			Err(format!("Number at {} ({}) is not a register!", r, register))
+	/// The number of addressable words, 32768.
+	pub const fn full_address_space() -> usize {
+		ADDRESS_SPACE
+	}
+
+	/// Makes [`Data::length_memory`] at least `length`, capped at
+	/// [`Data::full_address_space`]. Memory past the binary can always be read
+	/// and written, but the VM only executes it after expanding to it.
+	pub fn expand_to(&mut self, length: usize) {
+		self.expanded_length = self.expanded_length.max(length.min(ADDRESS_SPACE));
+	}
+
+	pub fn get_number(&self, i: usize) -> Result<u16, RuntimeError> {
+		match self.operand(i)? {
+			Operand::Literal(value) => Ok(value),
+			Operand::Register(index) => Ok(self.registers[index]),
+		}
+	}
+
+	pub fn set_number(&mut self, r: usize, value: u16) -> Result<(), RuntimeError> {
+		match self.operand(r)? {
+			Operand::Register(index) => {
+				self.registers[index] = value;
+				self.register_written(index);
+				Ok(())
+			}
+			Operand::Literal(literal) => Err(RuntimeError::NotARegister {
+				address: r,
+				value: literal,
+			}),
+		}
+	}
+
+	/// Reads the number at `i` as the spec says: 0-32767 are literals,
+	/// 32768-32775 are registers 0-7 and anything larger is invalid.
+	fn operand(&self, i: usize) -> Result<Operand, RuntimeError> {
+		match self.fetch(i as u16)? {
+			value @ 0..=32767 => Ok(Operand::Literal(value)),
+			value @ 32768..=32775 => Ok(Operand::Register(value as usize - 32768)),
+			value => Err(RuntimeError::NumberTooLarge {
+				address: i,
+				value,
+			}),
+		}
+	}
+
+	pub fn registers(&self) -> [u16; 8] {
+		self.registers
+	}
+
+	/// The values on the stack, with the top last.
+	pub fn stack(&self) -> &[u16] {
+		&self.stack
+	}
+
+	/// Every address written since the binary was loaded, with its value.
+	pub fn memory_changes(&self) -> &HashMap<usize, u16> {
+		&self.memory_changes
+	}
+
+	pub fn get_register(&self, index: usize) -> Result<u16, RuntimeError> {
+		self.registers
+			.get(index)
+			.cloned()
+			.ok_or(RuntimeError::RegisterOutOfRange(index))
+	}
+
+	pub fn set_register(&mut self, index: usize, value: u16) -> Result<(), RuntimeError> {
+		if value > 32767 {
+			return Err(RuntimeError::ValueTooLarge(value));
+		}
+		let register = self
+			.registers
+			.get_mut(index)
+			.ok_or(RuntimeError::RegisterOutOfRange(index))?;
+		*register = value;
+		self.register_written(index);
+		Ok(())
+	}
+
+	/// Calls `callback` with the index and new value whenever register
+	/// `index` is written, replacing any earlier callback for it.
+	pub fn on_register_write<F>(&mut self, index: usize, callback: F) -> Result<(), RuntimeError>
+	where
+		F: FnMut(usize, u16) + 'static,
+	{
+		let slot = self
+			.register_callbacks
+			.get_mut(index)
+			.ok_or(RuntimeError::RegisterOutOfRange(index))?;
+		*slot = Some(Rc::new(RefCell::new(callback)));
+		Ok(())
+	}
+
+	pub fn clear_register_callbacks(&mut self) {
+		self.register_callbacks = Default::default();
+	}
+
+	fn register_written(&mut self, index: usize) {
+		if let Some(callback) = &self.register_callbacks[index] {
+			(callback.borrow_mut())(index, self.registers[index]);
 		}
 	}
 
@@ -46,35 +189,75 @@ impl<'a> Data<'a> {
 		self.stack.push(value);
 	}
 
-	pub fn pop_stack(&mut self) -> Result<u16, String> {
-		self.stack
-			.pop()
-			.ok_or_else(|| "Stack was empty when popping!".to_string())
+	pub fn pop_stack(&mut self) -> Result<u16, RuntimeError> {
+		self.stack.pop().ok_or(RuntimeError::StackEmpty)
 	}
 
-	pub fn read_memory(&self, address: u16) -> Result<u16, String> {
+	pub fn read_memory(&mut self, address: u16) -> Result<u16, RuntimeError> {
+		let value = self.fetch(address)?;
+		self.memory_reads += 1;
+		self.log_access(address as usize, AccessKind::Read, value);
+		Ok(value)
+	}
+
+	pub(super) fn fetch(&self, address: u16) -> Result<u16, RuntimeError> {
 		let addr = address as usize;
 		if let Some(value) = self.memory_changes.get(&addr).cloned() {
 			Ok(value)
-		} else if let Some(value) = self.memory.get(address as usize).cloned() {
+		} else if let Some(value) = self.memory.get(addr).cloned() {
 			Ok(value)
+		} else if addr < ADDRESS_SPACE {
+			Ok(0)
 		} else {
-			Err(format!("Reading from out of range address {}!", addr))
+			Err(RuntimeError::ReadOutOfRange(addr))
 		}
 	}
 
-	pub fn write_memory(&mut self, address: u16, value: u16) -> Result<(), String> {
+	pub fn write_memory(&mut self, address: u16, value: u16) -> Result<(), RuntimeError> {
 		let addr = address as usize;
-		if addr < self.memory.len() {
+		if addr < ADDRESS_SPACE {
 			self.memory_changes.insert(addr, value);
+			self.memory_writes += 1;
+			self.log_access(addr, AccessKind::Write, value);
 			Ok(())
 		} else {
-			Err(format!("Writing to out of range address {}!", address))
+			Err(RuntimeError::WriteOutOfRange(addr))
 		}
 	}
 
 	pub fn length_memory(&self) -> usize {
-		self.memory.len()
+		self.memory.len().max(self.expanded_length)
+	}
+
+	/// Starts recording every `read_memory` and `write_memory` call.
+	pub fn enable_access_log(&mut self) {
+		self.access_log.get_or_insert_with(Vec::new);
+	}
+
+	pub fn access_log(&self) -> &[MemoryAccess] {
+		self.access_log.as_deref().unwrap_or(&[])
+	}
+
+	pub fn clear_access_log(&mut self) {
+		if let Some(log) = &mut self.access_log {
+			log.clear();
+		}
+	}
+
+	/// Sets the instruction pointer recorded with subsequent memory accesses.
+	pub fn set_current_ip(&mut self, ip: usize) {
+		self.current_ip = ip;
+	}
+
+	fn log_access(&mut self, addr: usize, kind: AccessKind, value: u16) {
+		if let Some(log) = &mut self.access_log {
+			log.push(MemoryAccess {
+				addr,
+				kind,
+				value,
+				instruction_pointer: self.current_ip,
+			});
+		}
 	}
 }
 
@@ -82,7 +265,7 @@ impl<'a> Data<'a> {
 mod tests {
 	use super::*;
 
-	const MEMORY: &'static [u16] = &[21, 19, 77, 0, 32768];
+	const MEMORY: &[u16] = &[21, 19, 77, 0, 32768];
 
 	#[test]
 	fn get_number() {
@@ -100,16 +283,66 @@ mod tests {
 		assert_eq!(data.get_number(4), Ok(0), "Reading a register address.");
 	}
 
+	#[test]
+	fn get_initial_register() {
+		let data = Data::with_registers(MEMORY, [5, 0, 0, 0, 0, 0, 0, 0]);
+		assert_eq!(data.get_number(4), Ok(5), "Register 0 should start at 5.");
+
+		let mut changes = HashMap::new();
+		changes.insert(2, 78);
+		let data = Data::from_state(MEMORY, [0; 8], vec![1, 2], changes);
+		assert_eq!(data.get_number(2), Ok(78));
+		assert_eq!(data.stack(), &[1, 2]);
+	}
+
 	#[test]
 	fn get_invalid() {
 		let data = Data::new(MEMORY);
 		assert_eq!(
-			data.get_number(5),
-			Err("Reading from out of range address 5!".to_string()),
+			data.get_number(32768),
+			Err(RuntimeError::ReadOutOfRange(32768)),
 			"Reading a register address."
 		);
 	}
 
+	#[test]
+	fn number_boundaries() {
+		const BOUNDARIES: &[u16] = &[32767, 32768, 32775, 32776];
+		let mut data = Data::new(BOUNDARIES);
+		data.set_register(7, 7).unwrap();
+		assert_eq!(data.get_number(0), Ok(32767), "32767 is a literal.");
+		assert_eq!(data.get_number(1), Ok(0), "32768 is register 0.");
+		assert_eq!(data.get_number(2), Ok(7), "32775 is register 7.");
+		assert_eq!(
+			data.get_number(3),
+			Err(RuntimeError::NumberTooLarge {
+				address: 3,
+				value: 32776
+			}),
+			"32776 is invalid."
+		);
+
+		assert_eq!(
+			data.set_number(0, 1),
+			Err(RuntimeError::NotARegister {
+				address: 0,
+				value: 32767
+			}),
+			"32767 is a literal."
+		);
+		assert_eq!(data.set_number(1, 1), Ok(()), "32768 is register 0.");
+		assert_eq!(data.set_number(2, 1), Ok(()), "32775 is register 7.");
+		assert_eq!(data.get_register(7), Ok(1));
+		assert_eq!(
+			data.set_number(3, 1),
+			Err(RuntimeError::NumberTooLarge {
+				address: 3,
+				value: 32776
+			}),
+			"32776 is invalid."
+		);
+	}
+
 	#[test]
 	fn update_register() {
 		const TEST_VALUE: u16 = 42;
@@ -128,10 +361,10 @@ mod tests {
 		let result = data.set_number(2, 42);
 		assert_eq!(
 			result,
-			Err(format!(
-				"Number at {} ({}) is not a register!",
-				2, MEMORY[2]
-			)),
+			Err(RuntimeError::NotARegister {
+				address: 2,
+				value: MEMORY[2],
+			}),
 			"Updating the value of a address that's not a register."
 		);
 	}
@@ -139,14 +372,29 @@ mod tests {
 	#[test]
 	fn update_invalid() {
 		let mut data = Data::new(MEMORY);
-		let result = data.set_number(5, 42);
+		let result = data.set_number(32768, 42);
 		assert_eq!(
 			result,
-			Err("Reading from out of range address 5!".to_string()),
+			Err(RuntimeError::ReadOutOfRange(32768)),
 			"Updating to an address out of range."
 		);
 	}
 
+	#[test]
+	fn set_register_directly() {
+		let mut data = Data::new(MEMORY);
+		data.set_register(0, 42).unwrap();
+		assert_eq!(data.get_number(4), Ok(42), "Register 0 should be set.");
+		assert_eq!(
+			data.set_register(8, 42),
+			Err(RuntimeError::RegisterOutOfRange(8)),
+		);
+		assert_eq!(
+			data.set_register(0, 32768),
+			Err(RuntimeError::ValueTooLarge(32768)),
+		);
+	}
+
 	#[test]
 	fn push_and_pop() {
 		const TEST_VALUE: u16 = 42;
@@ -163,16 +411,20 @@ mod tests {
 	fn only_pop() {
 		let mut data = Data::new(MEMORY);
 		let result = data.pop_stack();
+		assert!(
+			matches!(result, Err(RuntimeError::StackEmpty)),
+			"Poping when the stack is empty."
+		);
 		assert_eq!(
-			result,
+			result.map_err(String::from),
 			Err("Stack was empty when popping!".to_string()),
-			"Poping when the stack is empty."
+			"The message should be kept at the boundary."
 		);
 	}
 
 	#[test]
 	fn reading_memory() {
-		let data = Data::new(MEMORY);
+		let mut data = Data::new(MEMORY);
 		assert_eq!(
 			data.read_memory(2),
 			Ok(MEMORY[2]),
@@ -182,10 +434,10 @@ mod tests {
 
 	#[test]
 	fn reading_invalid_memory() {
-		let data = Data::new(MEMORY);
+		let mut data = Data::new(MEMORY);
 		assert_eq!(
-			data.read_memory(5),
-			Err("Reading from out of range address 5!".to_string()),
+			data.read_memory(32768),
+			Err(RuntimeError::ReadOutOfRange(32768)),
 			"Reading from out of range memory."
 		);
 	}
@@ -202,17 +454,96 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn memory_past_binary() {
+		let mut data = Data::new(MEMORY);
+		assert_eq!(
+			data.read_memory(32767),
+			Ok(0),
+			"Unwritten memory past the binary reads as 0."
+		);
+		data.write_memory(32767, 42).unwrap();
+		assert_eq!(
+			data.read_memory(32767),
+			Ok(42),
+			"Memory past the binary can be written."
+		);
+		assert_eq!(data.length_memory(), MEMORY.len());
+	}
+
+	#[test]
+	fn expand_to_full_address_space() {
+		let mut data = Data::new(MEMORY);
+		data.expand_to(40000);
+		assert_eq!(data.length_memory(), Data::full_address_space());
+		assert_eq!(data.read_memory(30000), Ok(0), "Unwritten memory is 0.");
+		data.write_memory(30000, 42).unwrap();
+		assert_eq!(data.read_memory(30000), Ok(42));
+		data.expand_to(10);
+		assert_eq!(
+			data.length_memory(),
+			32768,
+			"Expanding to less should do nothing."
+		);
+	}
+
+	#[test]
+	fn writing_up_to_address_space() {
+		let mut data = Data::new(MEMORY);
+		assert_eq!(data.write_memory(32767, 42), Ok(()));
+		assert_eq!(
+			data.write_memory(32768, 42),
+			Err(RuntimeError::WriteOutOfRange(32768)),
+			"Addresses are 15 bits."
+		);
+		assert_eq!(
+			data.write_memory(40000, 42),
+			Err(RuntimeError::WriteOutOfRange(40000)),
+			"Addresses are 15 bits."
+		);
+	}
+
 	#[test]
 	fn writing_invalid_memory() {
 		let mut data = Data::new(MEMORY);
-		let result = data.write_memory(5, 42);
+		let result = data.write_memory(32768, 42);
 		assert_eq!(
 			result,
-			Err("Writing to out of range address 5!".to_string()),
+			Err(RuntimeError::WriteOutOfRange(32768)),
 			"Writing to memory out of range."
 		);
 	}
 
+	#[test]
+	fn access_log() {
+		let mut data = Data::new(MEMORY);
+		data.read_memory(1).unwrap();
+		data.enable_access_log();
+		data.set_current_ip(3);
+		data.write_memory(2, 42).unwrap();
+		data.read_memory(2).unwrap();
+		assert_eq!(
+			data.access_log(),
+			&[
+				MemoryAccess {
+					addr: 2,
+					kind: AccessKind::Write,
+					value: 42,
+					instruction_pointer: 3,
+				},
+				MemoryAccess {
+					addr: 2,
+					kind: AccessKind::Read,
+					value: 42,
+					instruction_pointer: 3,
+				},
+			],
+			"Only accesses after enabling the log should be recorded."
+		);
+		data.clear_access_log();
+		assert!(data.access_log().is_empty(), "The log should be cleared.");
+	}
+
 	#[test]
 	fn length_memory() {
 		let data = Data::new(MEMORY);
@@ -0,0 +1,165 @@
+use std::{
+	fmt::Write as _,
+	io::{Read, Write},
+	str::FromStr,
+};
+
+use crate::runtime::vm::{BreakpointKind, StepOutcome, VM};
+
+/// One line of a debugger script.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+	/// `step [n]`: takes `n` steps, one by default, stopping early at
+	/// breakpoints.
+	Step(u64),
+	/// `continue`: runs until a breakpoint is hit or the program halts.
+	Continue,
+	/// `break <addr>`: stops before the instruction at `addr`.
+	Break(usize),
+	/// `regs`: prints every register.
+	Registers,
+	/// `print r<n>`: prints a register.
+	PrintRegister(usize),
+	/// `print <addr>`: prints a word of memory.
+	PrintMemory(u16),
+	/// `stack`: prints the stack, bottom first.
+	Stack,
+	/// `pc`: prints the address of the next instruction.
+	Pointer,
+	/// `jump <addr>`: makes `addr` the next instruction.
+	Jump(u16),
+}
+
+impl FromStr for Command {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut words = s.split_whitespace();
+		let name = words.next().unwrap_or("");
+		let argument = words.next();
+		if words.next().is_some() {
+			return Err(format!("Too many arguments to \"{}\"!", name));
+		}
+		let number = |arg: Option<&str>| {
+			arg.ok_or_else(|| format!("\"{}\" needs an argument!", name))
+				.and_then(|a| {
+					a.parse::<u16>()
+						.map_err(|_| format!("Could not parse \"{}\" as a number.", a))
+				})
+		};
+		match (name, argument) {
+			("step", None) => Ok(Command::Step(1)),
+			("step", Some(n)) => n
+				.parse()
+				.map(Command::Step)
+				.map_err(|_| format!("Could not parse \"{}\" as a step count.", n)),
+			("continue", None) => Ok(Command::Continue),
+			("break", arg) => number(arg).map(|a| Command::Break(a as usize)),
+			("regs", None) => Ok(Command::Registers),
+			("print", Some(r)) if r.starts_with('r') => r[1..]
+				.parse()
+				.ok()
+				.filter(|&i| i < 8)
+				.map(Command::PrintRegister)
+				.ok_or_else(|| format!("\"{}\" is not a register!", r)),
+			("print", arg) => number(arg).map(Command::PrintMemory),
+			("stack", None) => Ok(Command::Stack),
+			("pc", None) => Ok(Command::Pointer),
+			("jump", arg) => number(arg).map(Command::Jump),
+			("continue" | "regs" | "stack" | "pc", Some(_)) => {
+				Err(format!("\"{}\" doesn't take an argument!", name))
+			}
+			_ => Err(format!("Unknown debugger command \"{}\"!", name)),
+		}
+	}
+}
+
+impl Command {
+	/// Runs the command on `vm`, appending what it prints to `log`. Returns
+	/// whether the program is still running.
+	pub fn execute<I: Read, O: Write>(
+		self,
+		vm: &mut VM,
+		input: &mut I,
+		output: &mut O,
+		log: &mut String,
+	) -> Result<bool, String> {
+		let running = match self {
+			Command::Step(n) => vm.step_n(n, input, output)? != StepOutcome::Halted,
+			Command::Continue => loop {
+				if vm.step(input, output)?.halted {
+					break false;
+				}
+				if vm.at_breakpoint() {
+					break true;
+				}
+			},
+			Command::Break(addr) => {
+				vm.add_breakpoint(BreakpointKind::Address(addr));
+				true
+			}
+			Command::Registers => {
+				for (i, value) in vm.data.registers().iter().enumerate() {
+					let _ = writeln!(log, "r{}: {}", i, value);
+				}
+				true
+			}
+			Command::PrintRegister(i) => {
+				let _ = writeln!(log, "r{}: {}", i, vm.data.get_register(i)?);
+				true
+			}
+			Command::PrintMemory(addr) => {
+				let _ = writeln!(log, "{}: {}", addr, vm.data.fetch(addr)?);
+				true
+			}
+			Command::Stack => {
+				let _ = writeln!(log, "Stack: {:?}", vm.data.stack());
+				true
+			}
+			Command::Pointer => {
+				let _ = writeln!(log, "pc: {}", vm.pointer);
+				true
+			}
+			Command::Jump(addr) => {
+				if let Some(warning) = vm.set_pointer(addr)? {
+					let _ = writeln!(log, "Warning: {}", warning);
+				}
+				true
+			}
+		};
+		if !running {
+			log.push_str("Halted.\n");
+		} else if vm.at_breakpoint() && matches!(self, Command::Step(_) | Command::Continue) {
+			let _ = writeln!(log, "Breakpoint at {}.", vm.pointer);
+		}
+		vm.flush_output(output)?;
+		Ok(running)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_commands() {
+		assert_eq!("step".parse(), Ok(Command::Step(1)));
+		assert_eq!(" step  50 ".parse(), Ok(Command::Step(50)));
+		assert_eq!("break 1234".parse(), Ok(Command::Break(1234)));
+		assert_eq!("print r7".parse(), Ok(Command::PrintRegister(7)));
+		assert_eq!("print 20".parse(), Ok(Command::PrintMemory(20)));
+		assert_eq!("jump 3".parse(), Ok(Command::Jump(3)));
+		assert_eq!(
+			"print r8".parse::<Command>(),
+			Err("\"r8\" is not a register!".to_string())
+		);
+		assert_eq!(
+			"regs r0".parse::<Command>(),
+			Err("\"regs\" doesn't take an argument!".to_string())
+		);
+		assert_eq!(
+			"goto 3".parse::<Command>(),
+			Err("Unknown debugger command \"goto\"!".to_string())
+		);
+	}
+}
@@ -1,5 +1,9 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	str::FromStr,
+};
 
+#[derive(Default)]
 pub struct HeaderCollection(HashMap<String, String>);
 
 impl HeaderCollection {
@@ -7,35 +11,51 @@ impl HeaderCollection {
 		HeaderCollection(HashMap::new())
 	}
 
+	/// Adds a `Name: value` line. Names are case-insensitive, so they're
+	/// stored in lowercase.
 	pub fn add(&mut self, line: &str) -> Result<(), String> {
-		let mut a = line.trim().split(": ");
+		let mut a = line.trim().splitn(2, ": ");
 		let name = a
 			.next()
 			.filter(|n| !n.is_empty())
 			.ok_or_else(|| format!("No header name found in:\n\t{}", line))?;
-		if self.0.contains_key(name) {
-			Err(format!(
+		match self.0.entry(name.to_lowercase()) {
+			Entry::Occupied(_) => Err(format!(
 				"Header collection already contains key \"{}\"!",
 				name,
-			))
-		} else {
-			self.0.insert(
-				name.into(),
-				a.next()
-					.ok_or_else(|| format!("No header value found in:\n\t{}", line))?
-					.into(),
-			);
-			Ok(())
+			)),
+			Entry::Vacant(entry) => {
+				entry.insert(
+					a.next()
+						.ok_or_else(|| format!("No header value found in:\n\t{}", line))?
+						.into(),
+				);
+				Ok(())
+			}
 		}
 	}
 
 	pub fn get<T: FromStr>(&self, name: &str) -> Option<Result<T, <T as FromStr>::Err>> {
-		self.0.get(name).map(|v| v.parse::<T>())
+		self.0.get(&name.to_lowercase()).map(|v| v.parse::<T>())
 	}
 
 	pub fn clear(&mut self) {
 		self.0.clear()
 	}
+
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Iterates over the headers as `(name, value)`, with lowercase names, in
+	/// no particular order.
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+	}
 }
 
 #[cfg(test)]
@@ -54,6 +74,17 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn add_value_with_separator() {
+		let mut hc = HeaderCollection::new();
+		hc.add("X-Path: a: b: c\r\n").unwrap();
+		assert_eq!(
+			hc.get::<String>("X-Path"),
+			Some(Ok("a: b: c".to_string())),
+			"Only the first \": \" should separate the name from the value.",
+		);
+	}
+
 	#[test]
 	fn add_without_name() {
 		let mut hc = HeaderCollection::new();
@@ -88,6 +119,46 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn get_any_case() {
+		let mut hc = HeaderCollection::new();
+		hc.add("Content-Length: 119\r\n").unwrap();
+		assert_eq!(hc.get("content-length"), Some(Ok(119)));
+		assert_eq!(hc.get("CONTENT-LENGTH"), Some(Ok(119)));
+	}
+
+	#[test]
+	fn add_twice_other_case() {
+		let mut hc = HeaderCollection::new();
+		hc.add("Content-Length: 119\r\n").unwrap();
+		let second_result = hc.add("content-length: 197\r\n");
+		assert_eq!(
+			second_result,
+			Err("Header collection already contains key \"content-length\"!".to_string()),
+			"Header names should be case-insensitive.",
+		);
+	}
+
+	#[test]
+	fn len_and_iter() {
+		let mut hc = HeaderCollection::new();
+		assert!(hc.is_empty());
+		hc.add("Content-Length: 119\r\n").unwrap();
+		hc.add("Content-Type: application/json\r\n").unwrap();
+		assert_eq!(hc.len(), 2);
+		assert!(!hc.is_empty());
+		let mut headers = hc.iter().collect::<Vec<_>>();
+		headers.sort();
+		assert_eq!(
+			headers,
+			vec![
+				("content-length", "119"),
+				("content-type", "application/json")
+			],
+			"Iteration should visit every header.",
+		);
+	}
+
 	#[test]
 	fn clear() {
 		let mut hc = HeaderCollection::new();
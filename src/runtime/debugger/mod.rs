@@ -1 +1,6 @@
+mod command;
 mod header_collection;
+mod watch;
+pub use command::Command;
+pub use header_collection::HeaderCollection;
+pub use watch::{Expression, Watches};
@@ -0,0 +1,161 @@
+use std::{iter::Peekable, str::Chars};
+
+use crate::{runtime::data::Data, text::skip_whitespace};
+
+/// An expression over the VM's state, such as `r0`, `[r1 + 2]` or `r2 - 1`.
+/// Arithmetic wraps modulo 32768 like the VM's.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+	Literal(u16),
+	Register(usize),
+	/// The word of memory at an address.
+	Memory(Box<Expression>),
+	Add(Box<Expression>, Box<Expression>),
+	Sub(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let mut chars = s.chars().peekable();
+		let expression = sum(&mut chars)?;
+		match skip_whitespace(&mut chars) {
+			None => Ok(expression),
+			Some(c) => Err(format!("Unexpected '{}' in \"{}\"!", c, s)),
+		}
+	}
+
+	pub fn evaluate(&self, data: &Data) -> Result<u16, String> {
+		Ok(match self {
+			Expression::Literal(value) => *value,
+			Expression::Register(index) => data.get_register(*index)?,
+			Expression::Memory(address) => data.fetch(address.evaluate(data)?)?,
+			Expression::Add(a, b) => (a.evaluate(data)? + b.evaluate(data)?) % 32768,
+			Expression::Sub(a, b) => (a.evaluate(data)? + 32768 - b.evaluate(data)?) % 32768,
+		})
+	}
+}
+
+fn sum(chars: &mut Peekable<Chars>) -> Result<Expression, String> {
+	let mut expression = atom(chars)?;
+	loop {
+		match skip_whitespace(chars) {
+			Some('+') => {
+				chars.next();
+				expression = Expression::Add(Box::new(expression), Box::new(atom(chars)?));
+			}
+			Some('-') => {
+				chars.next();
+				expression = Expression::Sub(Box::new(expression), Box::new(atom(chars)?));
+			}
+			_ => return Ok(expression),
+		}
+	}
+}
+
+fn atom(chars: &mut Peekable<Chars>) -> Result<Expression, String> {
+	match skip_whitespace(chars) {
+		Some('[') => {
+			chars.next();
+			let address = sum(chars)?;
+			match skip_whitespace(chars) {
+				Some(']') => {
+					chars.next();
+					Ok(Expression::Memory(Box::new(address)))
+				}
+				_ => Err("Missing ']'!".to_string()),
+			}
+		}
+		Some('r') => {
+			chars.next();
+			match chars.next().and_then(|c| c.to_digit(10)) {
+				Some(index @ 0..=7) => Ok(Expression::Register(index as usize)),
+				_ => Err("Registers are r0 to r7!".to_string()),
+			}
+		}
+		Some(c) if c.is_ascii_digit() => {
+			let mut digits = String::new();
+			while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+				digits.push(c);
+				chars.next();
+			}
+			digits
+				.parse()
+				.ok()
+				.filter(|&v| v < 32768)
+				.map(Expression::Literal)
+				.ok_or_else(|| format!("{} is too large!", digits))
+		}
+		Some(c) => Err(format!("Unexpected '{}'!", c)),
+		None => Err("Expected a value!".to_string()),
+	}
+}
+
+/// Expressions to re-evaluate after every step, reporting the ones whose
+/// value changed.
+#[derive(Clone, Debug, Default)]
+pub struct Watches {
+	watches: Vec<(String, Expression, Option<u16>)>,
+}
+
+impl Watches {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Starts watching `expression`, from its current value in `data`.
+	pub fn add(&mut self, expression: &str, data: &Data) -> Result<(), String> {
+		let parsed = Expression::parse(expression)?;
+		let value = parsed.evaluate(data).ok();
+		self.watches
+			.push((expression.trim().to_string(), parsed, value));
+		Ok(())
+	}
+
+	/// Re-evaluates every watch, returning the text and new value of those
+	/// that changed since the last poll. Watches that can't be evaluated,
+	/// such as a read past the end of memory, are skipped.
+	pub fn poll(&mut self, data: &Data) -> Vec<(String, u16)> {
+		let mut changed = Vec::new();
+		for (text, expression, last) in &mut self.watches {
+			if let Ok(value) = expression.evaluate(data) {
+				if *last != Some(value) {
+					*last = Some(value);
+					changed.push((text.clone(), value));
+				}
+			}
+		}
+		changed
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::runtime::vm::VM;
+
+	#[test]
+	fn evaluate_expressions() {
+		let mut data = Data::new(&[7, 8, 9]);
+		data.set_register(1, 1).unwrap();
+		let evaluate = |s: &str| Expression::parse(s).and_then(|e| e.evaluate(&data));
+		assert_eq!(evaluate("[r1 + 1]"), Ok(9));
+		assert_eq!(evaluate("r0 - 1"), Ok(32767), "Subtraction should wrap.");
+		assert_eq!(evaluate("r8"), Err("Registers are r0 to r7!".to_string()));
+		assert_eq!(evaluate("[r1"), Err("Missing ']'!".to_string()));
+	}
+
+	#[test]
+	fn report_changes_once() {
+		// 0: set r0 5, noop, halt
+		let mut vm = VM::new(Data::new(&[1, 32768, 5, 21, 0]));
+		let mut watches = Watches::new();
+		watches.add("r0", &vm.data).unwrap();
+		assert_eq!(watches.poll(&vm.data), vec![]);
+		vm.step(&mut std::io::empty(), &mut std::io::sink())
+			.unwrap();
+		assert_eq!(watches.poll(&vm.data), vec![("r0".to_string(), 5)]);
+		vm.step(&mut std::io::empty(), &mut std::io::sink())
+			.unwrap();
+		assert_eq!(watches.poll(&vm.data), vec![], "It only changed once.");
+	}
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+/// What went wrong when the runtime accessed its memory, registers or stack.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+	StackEmpty,
+	ReadOutOfRange(usize),
+	WriteOutOfRange(usize),
+	NumberTooLarge {
+		address: usize,
+		value: u16,
+	},
+	NotARegister {
+		address: usize,
+		value: u16,
+	},
+	RegisterOutOfRange(usize),
+	ValueTooLarge(u16),
+	UnknownOpcode(u16),
+	ModByZero,
+	Io(String),
+	InvalidSave(String),
+	IncompatibleSaveVersion {
+		expected: u8,
+		found: u8,
+	},
+	/// The VM came back to the same state after `period` steps.
+	InfiniteLoop {
+		period: u64,
+	},
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RuntimeError::StackEmpty => write!(f, "Stack was empty when popping!"),
+			RuntimeError::ReadOutOfRange(address) => {
+				write!(f, "Reading from out of range address {}!", address)
+			}
+			RuntimeError::WriteOutOfRange(address) => {
+				write!(f, "Writing to out of range address {}!", address)
+			}
+			RuntimeError::NumberTooLarge {
+				address,
+				value,
+			} => {
+				write!(f, "Number at {} ({}) is too large!", address, value)
+			}
+			RuntimeError::NotARegister {
+				address,
+				value,
+			} => {
+				write!(f, "Number at {} ({}) is not a register!", address, value)
+			}
+			RuntimeError::RegisterOutOfRange(index) => {
+				write!(f, "Register {} is out of range 0-7!", index)
+			}
+			RuntimeError::ValueTooLarge(value) => {
+				write!(f, "Value {} is too large for a register!", value)
+			}
+			RuntimeError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {}!", opcode),
+			RuntimeError::ModByZero => write!(f, "Division by zero!"),
+			RuntimeError::Io(message) => write!(f, "{}", message),
+			RuntimeError::InvalidSave(reason) => write!(f, "Invalid save file! {}", reason),
+			RuntimeError::IncompatibleSaveVersion {
+				expected,
+				found,
+			} => {
+				let oldest = super::vm::MIN_SAVE_FORMAT_VERSION;
+				let supported = if oldest == *expected {
+					format!("version {}", expected)
+				} else {
+					format!("versions {} to {}", oldest, expected)
+				};
+				write!(
+					f,
+					"The save file is from format version {}, but only {} can be loaded. Was it \
+					 made by a different build?",
+					found, supported
+				)
+			}
+			RuntimeError::InfiniteLoop {
+				period,
+			} => write!(f, "Stuck in a loop repeating every {} steps!", period),
+		}
+	}
+}
+
+impl From<RuntimeError> for String {
+	fn from(error: RuntimeError) -> Self {
+		error.to_string()
+	}
+}
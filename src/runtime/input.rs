@@ -0,0 +1,366 @@
+use std::{
+	collections::VecDeque,
+	io::{self, IsTerminal, Read},
+	str::FromStr,
+	sync::{Arc, Condvar, Mutex, MutexGuard},
+	time::Duration,
+};
+
+/// What to do with input bytes outside of ASCII, such as UTF-8 encoded
+/// accented characters typed into a terminal.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NonAsciiPolicy {
+	/// Hand every byte to the program as is, so a multi-byte character is
+	/// read as several `in` instructions.
+	#[default]
+	Pass,
+	/// Drop each multi-byte character whole, with a warning on stderr.
+	Drop,
+}
+
+impl FromStr for NonAsciiPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"pass" => Ok(NonAsciiPolicy::Pass),
+			"drop" => Ok(NonAsciiPolicy::Drop),
+			_ => Err(format!("Unknown non-ASCII policy \"{}\"!", s)),
+		}
+	}
+}
+
+/// Applies a [`NonAsciiPolicy`] to the bytes read from `inner`.
+pub struct NonAsciiFilter<R> {
+	inner: R,
+	policy: NonAsciiPolicy,
+}
+
+impl<R: Read> NonAsciiFilter<R> {
+	pub fn new(inner: R, policy: NonAsciiPolicy) -> Self {
+		Self {
+			inner,
+			policy,
+		}
+	}
+
+	fn read_byte(&mut self) -> io::Result<Option<u8>> {
+		let mut buf = [0];
+		match self.inner.read(&mut buf)? {
+			0 => Ok(None),
+			_ => Ok(Some(buf[0])),
+		}
+	}
+}
+
+/// The number of bytes in a UTF-8 sequence, judging by its first byte.
+fn sequence_length(first: u8) -> usize {
+	match first {
+		0xC0..=0xDF => 2,
+		0xE0..=0xEF => 3,
+		0xF0..=0xF7 => 4,
+		_ => 1,
+	}
+}
+
+impl<R: Read> Read for NonAsciiFilter<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.policy == NonAsciiPolicy::Pass {
+			return self.inner.read(buf);
+		}
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		// One byte at a time, so that a terminal is never waited on for more
+		// than what was asked for.
+		while let Some(byte) = self.read_byte()? {
+			if byte.is_ascii() {
+				buf[0] = byte;
+				return Ok(1);
+			}
+			for _ in 1..sequence_length(byte) {
+				if self.read_byte()?.is_none() {
+					break;
+				}
+			}
+			eprintln!("Warning: Dropped a non-ASCII character from the input.");
+		}
+		Ok(0)
+	}
+}
+
+/// The line endings of the input, which the program always sees as LF.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+	/// Lines end with CR LF, or just LF, and every CR is dropped.
+	#[default]
+	CrLf,
+	/// Lines end with LF, and every byte is passed on, CR included.
+	Lf,
+	/// Lines end with CR, which is passed on as LF.
+	Cr,
+}
+
+impl FromStr for LineEnding {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"crlf" => Ok(LineEnding::CrLf),
+			"lf" => Ok(LineEnding::Lf),
+			"cr" => Ok(LineEnding::Cr),
+			_ => Err(format!("Unknown line ending \"{}\"!", s)),
+		}
+	}
+}
+
+/// Translates the line endings read from `inner` to LF.
+pub struct LineEndingInput<R> {
+	inner: R,
+	line_ending: LineEnding,
+}
+
+impl<R: Read> LineEndingInput<R> {
+	pub fn new(inner: R, line_ending: LineEnding) -> Self {
+		Self {
+			inner,
+			line_ending,
+		}
+	}
+}
+
+impl<R: Read> Read for LineEndingInput<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let count = self.inner.read(buf)?;
+			match self.line_ending {
+				LineEnding::Lf => return Ok(count),
+				LineEnding::Cr => {
+					for b in buf[..count].iter_mut().filter(|b| **b == b'\r') {
+						*b = b'\n';
+					}
+					return Ok(count);
+				}
+				LineEnding::CrLf => {
+					let mut kept = 0;
+					for i in 0..count {
+						if buf[i] != b'\r' {
+							buf[kept] = buf[i];
+							kept += 1;
+						}
+					}
+					// Only CRs were read, so read again rather than looking like
+					// the end of the input.
+					if kept > 0 || count == 0 {
+						return Ok(kept);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Queues bytes for a VM to read ahead of its real input, see
+/// [`super::vm::VM::with_injector`]. Clones queue to the same VM, and can be
+/// sent to other threads. Sending wakes a VM blocked at the end of its input,
+/// see [`super::vm::EofPolicy::Block`], and closing tells it nothing more is
+/// coming.
+///
+/// Reading from an injector takes what's queued, waiting for bytes to be sent,
+/// and ends once it's closed and empty.
+#[derive(Clone, Debug, Default)]
+pub struct InputInjector {
+	shared: Arc<(Mutex<Queue>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+	bytes: VecDeque<u8>,
+	closed: bool,
+}
+
+impl InputInjector {
+	pub fn send(&self, bytes: &[u8]) {
+		let (queue, sent) = &*self.shared;
+		lock(queue).bytes.extend(bytes);
+		sent.notify_all();
+	}
+
+	/// Marks the end of what will be sent.
+	pub fn close(&self) {
+		let (queue, sent) = &*self.shared;
+		lock(queue).closed = true;
+		sent.notify_all();
+	}
+
+	/// Blocks until there are bytes in the queue, the injector is closed, or
+	/// `timeout` has passed. Returns whether there are bytes to read.
+	pub(super) fn wait(&self, timeout: Duration) -> bool {
+		let (queue, sent) = &*self.shared;
+		let (queue, _) = sent
+			.wait_timeout_while(lock(queue), timeout, |q| q.bytes.is_empty() && !q.closed)
+			.unwrap_or_else(|e| e.into_inner());
+		!queue.bytes.is_empty()
+	}
+
+	pub(super) fn is_closed(&self) -> bool {
+		lock(&self.shared.0).closed
+	}
+
+	/// Moves as many queued bytes as fit into `buf`, returning how many.
+	fn take(&self, buf: &mut [u8]) -> usize {
+		take_from(&mut lock(&self.shared.0), buf)
+	}
+}
+
+fn lock(queue: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+	queue.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn take_from(queue: &mut Queue, buf: &mut [u8]) -> usize {
+	let count = buf.len().min(queue.bytes.len());
+	for (b, injected) in buf.iter_mut().zip(queue.bytes.drain(..count)) {
+		*b = injected;
+	}
+	count
+}
+
+impl Read for InputInjector {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let (queue, sent) = &*self.shared;
+		let mut queue = sent
+			.wait_while(lock(queue), |q| q.bytes.is_empty() && !q.closed)
+			.unwrap_or_else(|e| e.into_inner());
+		Ok(take_from(&mut queue, buf))
+	}
+}
+
+/// Reads the bytes queued in `injector` before those of `inner`.
+pub(super) struct InjectedInput<'q, R> {
+	inner: R,
+	injector: Option<&'q InputInjector>,
+}
+
+impl<'q, R: Read> InjectedInput<'q, R> {
+	pub(super) fn new(inner: R, injector: Option<&'q InputInjector>) -> Self {
+		Self {
+			inner,
+			injector,
+		}
+	}
+}
+
+impl<'q, R: Read> Read for InjectedInput<'q, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self.injector.map(|injector| injector.take(buf)) {
+			Some(count) if count > 0 => Ok(count),
+			_ => self.inner.read(buf),
+		}
+	}
+}
+
+/// Stdin with `policy` applied, read through a line editor when it's a
+/// terminal and the `lineeditor` feature is on.
+pub fn stdin(policy: NonAsciiPolicy) -> Result<Box<dyn Read + Send>, String> {
+	if io::stdin().is_terminal() {
+		#[cfg(feature = "lineeditor")]
+		return Ok(Box::new(NonAsciiFilter::new(
+			super::line_editor::LineEditor::new()?,
+			policy,
+		)));
+	}
+	Ok(Box::new(NonAsciiFilter::new(io::stdin(), policy)))
+}
+
+/// An endless stream of pseudo-random bytes, the same for every reader with
+/// the same seed. For fuzzing how a program handles its input.
+#[derive(Clone, Debug)]
+pub struct RandomInput {
+	state: u64,
+}
+
+impl RandomInput {
+	pub fn new(seed: u64) -> Self {
+		Self {
+			state: seed,
+		}
+	}
+
+	/// The next number of the SplitMix64 sequence.
+	fn next(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+}
+
+impl Read for RandomInput {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		for chunk in buf.chunks_mut(8) {
+			let bytes = self.next().to_le_bytes();
+			chunk.copy_from_slice(&bytes[..chunk.len()]);
+		}
+		Ok(buf.len())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read_all(input: &[u8], policy: NonAsciiPolicy) -> Vec<u8> {
+		let mut output = Vec::new();
+		NonAsciiFilter::new(input, policy)
+			.read_to_end(&mut output)
+			.unwrap();
+		output
+	}
+
+	#[test]
+	fn same_seed_same_bytes() {
+		let read = |seed, length| {
+			let mut bytes = vec![0; length];
+			RandomInput::new(seed).read_exact(&mut bytes).unwrap();
+			bytes
+		};
+		assert_eq!(read(42, 100), read(42, 100));
+		assert_eq!(
+			read(42, 100)[..13],
+			read(42, 13)[..],
+			"A shorter read should start the same."
+		);
+		assert_ne!(read(42, 100), read(43, 100));
+	}
+
+	#[test]
+	fn read_injector_until_closed() {
+		let injector = InputInjector::default();
+		let sender = injector.clone();
+		let sending = std::thread::spawn(move || {
+			sender.send(b"save.bin\n");
+			sender.close();
+		});
+		let mut read = String::new();
+		injector.clone().read_to_string(&mut read).unwrap();
+		sending.join().unwrap();
+		assert_eq!(read, "save.bin\n");
+	}
+
+	#[test]
+	fn two_byte_character() {
+		let input = "café\n".as_bytes();
+		assert_eq!(input.len(), 6, "é should be two bytes.");
+		assert_eq!(
+			read_all(input, NonAsciiPolicy::Pass),
+			input,
+			"Passing should keep every byte."
+		);
+		assert_eq!(
+			read_all(input, NonAsciiPolicy::Drop),
+			b"caf\n",
+			"Dropping should remove both bytes of é."
+		);
+	}
+}
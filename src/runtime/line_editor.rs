@@ -0,0 +1,92 @@
+use std::{
+	collections::VecDeque,
+	io::{self, Read},
+};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+/// A line buffered `Read` over the terminal, with readline-style editing and
+/// history.
+pub struct LineEditor {
+	editor: DefaultEditor,
+	buffer: VecDeque<u8>,
+}
+
+impl LineEditor {
+	pub fn new() -> Result<Self, String> {
+		Ok(Self {
+			editor: DefaultEditor::new()
+				.map_err(|e| format!("Could not create line editor. {}", e))?,
+			buffer: VecDeque::new(),
+		})
+	}
+
+	pub fn add_history(&mut self, line: &str) -> Result<(), String> {
+		self.editor
+			.add_history_entry(line)
+			.map(|_| ())
+			.map_err(|e| format!("Could not add \"{}\" to history. {}", line, e))
+	}
+
+	pub fn history(&self) -> Vec<String> {
+		self.editor.history().iter().cloned().collect()
+	}
+
+	/// Queues every line in the history as input, as if they had been typed
+	/// again.
+	pub fn replay_history(&mut self) {
+		for line in self.editor.history().iter() {
+			self.buffer.extend(line.bytes());
+			self.buffer.push_back(b'\n');
+		}
+	}
+
+	fn fill_buffer(&mut self) -> io::Result<bool> {
+		match self.editor.readline("") {
+			Ok(line) => {
+				if !line.is_empty() {
+					self.add_history(&line).map_err(io::Error::other)?;
+				}
+				self.buffer.extend(line.bytes());
+				self.buffer.push_back(b'\n');
+				Ok(true)
+			}
+			Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(false),
+			Err(e) => Err(io::Error::other(e)),
+		}
+	}
+}
+
+impl Read for LineEditor {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.buffer.is_empty() && !self.fill_buffer()? {
+			return Ok(0);
+		}
+		let count = buf.len().min(self.buffer.len());
+		for (b, v) in buf.iter_mut().zip(self.buffer.drain(..count)) {
+			*b = v;
+		}
+		Ok(count)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn replay_injected_history() {
+		let mut editor = LineEditor::new().unwrap();
+		editor.add_history("take tablet").unwrap();
+		editor.add_history("use tablet").unwrap();
+		assert_eq!(editor.history(), vec!["take tablet", "use tablet"]);
+
+		editor.replay_history();
+		let mut replayed = [0; 23];
+		editor.read_exact(&mut replayed).unwrap();
+		assert_eq!(
+			&replayed, b"take tablet\nuse tablet\n",
+			"History should be replayed as input, one line at a time."
+		);
+	}
+}
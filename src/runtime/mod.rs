@@ -1,3 +1,8 @@
 pub mod data;
 pub mod debugger;
+pub mod error;
+pub mod input;
+#[cfg(feature = "lineeditor")]
+pub mod line_editor;
+pub mod output;
 pub mod vm;
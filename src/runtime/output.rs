@@ -0,0 +1,314 @@
+use std::{
+	convert::TryFrom,
+	io::{self, Write},
+	str::{self, FromStr},
+};
+
+use super::input::LineEnding;
+
+/// Writes everything to both `first` and `second`, such as the terminal and
+/// a transcript file.
+pub struct Tee<A, B> {
+	first: A,
+	second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+	pub fn new(first: A, second: B) -> Self {
+		Self {
+			first,
+			second,
+		}
+	}
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		// Everything is written to both, so that neither falls behind when
+		// the other takes a partial write.
+		self.first.write_all(buf)?;
+		self.second.write_all(buf)?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.first.flush()?;
+		self.second.flush()
+	}
+}
+
+/// How the VM translates newlines, for hosts that don't use LF.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NewlineMode {
+	/// Input and output are passed on as is.
+	#[default]
+	Raw,
+	/// CR LF in the input is read as LF, see
+	/// [`super::input::LineEnding::CrLf`].
+	NormalizeLf,
+	/// Every LF written is expanded to CR LF.
+	TranslateLfToCrLf,
+}
+
+impl NewlineMode {
+	/// The line ending of the input that goes with the mode, if it has one.
+	pub(super) fn line_ending(self) -> Option<LineEnding> {
+		match self {
+			NewlineMode::Raw => Some(LineEnding::Lf),
+			NewlineMode::NormalizeLf => Some(LineEnding::CrLf),
+			NewlineMode::TranslateLfToCrLf => None,
+		}
+	}
+}
+
+impl FromStr for NewlineMode {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"raw" => Ok(NewlineMode::Raw),
+			"lf" => Ok(NewlineMode::NormalizeLf),
+			"crlf" => Ok(NewlineMode::TranslateLfToCrLf),
+			_ => Err(format!("Unknown newline mode \"{}\"!", s)),
+		}
+	}
+}
+
+/// Expands every LF written to `inner` to CR LF when `translate` is set.
+pub(super) struct CrLfOutput<W> {
+	inner: W,
+	translate: bool,
+}
+
+impl<W: Write> CrLfOutput<W> {
+	pub(super) fn new(inner: W, translate: bool) -> Self {
+		Self {
+			inner,
+			translate,
+		}
+	}
+}
+
+impl<W: Write> Write for CrLfOutput<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if !self.translate {
+			return self.inner.write(buf);
+		}
+		let mut lines = buf.split(|&b| b == b'\n');
+		if let Some(first) = lines.next() {
+			self.inner.write_all(first)?;
+		}
+		for line in lines {
+			self.inner.write_all(b"\r\n")?;
+			self.inner.write_all(line)?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// What `out` writes for the characters it's given.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutFormat {
+	/// The character itself.
+	#[default]
+	Char,
+	/// The character's code in decimal.
+	Dec,
+	/// The character's code in hex.
+	Hex,
+}
+
+impl FromStr for OutFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"char" => Ok(OutFormat::Char),
+			"dec" => Ok(OutFormat::Dec),
+			"hex" => Ok(OutFormat::Hex),
+			_ => Err(format!("Unknown out format \"{}\"!", s)),
+		}
+	}
+}
+
+/// Writes the code of every character written to `inner` as a number,
+/// separated by spaces, unless the format is [`OutFormat::Char`].
+/// `started` is whether a number has been written before, so that it
+/// carries over between writers.
+pub(super) struct NumericOutput<'s, W> {
+	inner: W,
+	format: OutFormat,
+	started: &'s mut bool,
+}
+
+impl<'s, W: Write> NumericOutput<'s, W> {
+	pub(super) fn new(inner: W, format: OutFormat, started: &'s mut bool) -> Self {
+		Self {
+			inner,
+			format,
+			started,
+		}
+	}
+}
+
+impl<'s, W: Write> Write for NumericOutput<'s, W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.format == OutFormat::Char {
+			return self.inner.write(buf);
+		}
+		let text =
+			str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		for c in text.chars() {
+			if *self.started {
+				self.inner.write_all(b" ")?;
+			}
+			match self.format {
+				OutFormat::Hex => write!(self.inner, "{:02X}", c as u32)?,
+				_ => write!(self.inner, "{}", c as u32)?,
+			}
+			*self.started = true;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// Holds output in `buffer` until it grows to `capacity`. Flushes are
+/// ignored, so the output only reaches `inner` a buffer at a time or when
+/// [`OutputBuffer::drain`] is called.
+pub(super) struct OutputBuffer<'b, W> {
+	buffer: &'b mut Vec<u8>,
+	capacity: usize,
+	inner: W,
+}
+
+impl<'b, W: Write> OutputBuffer<'b, W> {
+	pub(super) fn new(buffer: &'b mut Vec<u8>, capacity: usize, inner: W) -> Self {
+		Self {
+			buffer,
+			capacity,
+			inner,
+		}
+	}
+
+	/// Writes everything held to `inner`, and flushes it.
+	pub(super) fn drain(&mut self) -> io::Result<()> {
+		self.inner.write_all(self.buffer)?;
+		self.buffer.clear();
+		self.inner.flush()
+	}
+}
+
+impl<'b, W: Write> Write for OutputBuffer<'b, W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.buffer.extend_from_slice(buf);
+		if self.buffer.len() >= self.capacity {
+			self.drain()?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Counts the bytes written to `inner`, and fails once `limit` of them have
+/// been written. A write that would go past the limit is cut short.
+pub(super) struct OutputLimit<W> {
+	inner: W,
+	written: u64,
+	limit: u64,
+	exceeded: bool,
+}
+
+impl<W: Write> OutputLimit<W> {
+	/// Starts counting from `written` bytes.
+	pub(super) fn new(inner: W, written: u64, limit: u64) -> Self {
+		Self {
+			inner,
+			written,
+			limit,
+			exceeded: false,
+		}
+	}
+
+	pub(super) fn written(&self) -> u64 {
+		self.written
+	}
+
+	/// Whether anything was refused for going past the limit.
+	pub(super) fn exceeded(&self) -> bool {
+		self.exceeded
+	}
+}
+
+impl<W: Write> Write for OutputLimit<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let remaining = self.limit.saturating_sub(self.written);
+		if remaining == 0 && !buf.is_empty() {
+			self.exceeded = true;
+			return Err(io::Error::other("Output limit exceeded."));
+		}
+		let end = buf
+			.len()
+			.min(usize::try_from(remaining).unwrap_or(usize::MAX));
+		let written = self.inner.write(&buf[..end])?;
+		self.written += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn both_sinks() {
+		let mut first = Vec::new();
+		let mut second = Vec::new();
+		let mut tee = Tee::new(&mut first, &mut second);
+		tee.write_all(b"Hello, ").unwrap();
+		tee.write_all(b"world!\n").unwrap();
+		tee.flush().unwrap();
+		assert_eq!(
+			first, b"Hello, world!\n",
+			"The first sink should get every byte."
+		);
+		assert_eq!(second, first, "The second sink should get the same bytes.");
+	}
+
+	#[test]
+	fn expand_newlines() {
+		let mut out = Vec::new();
+		CrLfOutput::new(&mut out, true)
+			.write_all(b"\nab\n\nc")
+			.unwrap();
+		assert_eq!(out, b"\r\nab\r\n\r\nc");
+	}
+
+	#[test]
+	fn limit_cuts_output_short() {
+		let mut out = Vec::new();
+		let mut limited = OutputLimit::new(&mut out, 1, 4);
+		assert!(limited.write_all(b"ab").is_ok());
+		assert!(limited.write_all(b"cd").is_err());
+		assert!(limited.exceeded());
+		assert_eq!(limited.written(), 4);
+		assert_eq!(
+			out, b"abc",
+			"Only the bytes under the limit should be written."
+		);
+	}
+}
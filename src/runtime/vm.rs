@@ -1,27 +1,269 @@
 use std::{
-	io::{Read, Write},
+	collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+	convert::TryFrom,
+	hash::{Hash, Hasher},
+	io::{self, Read, Write},
+	rc::Rc,
+	str::FromStr,
 	sync::{
-		atomic::{AtomicBool, Ordering},
+		atomic::{AtomicBool, AtomicU64, Ordering},
 		Arc,
+		OnceLock,
 	},
+	time::{Duration, Instant},
 };
 
-use serde::{Deserialize, Serialize};
-
-use super::data::Data;
+use super::{
+	data::Data,
+	debugger::Command,
+	error::RuntimeError,
+	input::{self, InjectedInput, InputInjector, LineEnding, LineEndingInput, NonAsciiPolicy},
+	output::{CrLfOutput, NewlineMode, NumericOutput, OutFormat, OutputBuffer, OutputLimit},
+};
+use crate::{compiler::disasm::Disassembler, opcode, source_map::SourceMap};
 
 type Handler<I, O> = fn(&mut Data, usize, &mut I, &mut O) -> Result<Action, String>;
 
+/// Called with the pointer and opcode before an instruction runs. Shared
+/// between clones of [`VM`].
+type PreHook = Rc<dyn Fn(usize, u16)>;
+/// Called with the pointer, opcode and resulting data after an instruction
+/// runs.
+type PostHook = Rc<dyn Fn(usize, u16, &Data)>;
+
+/// How often [`EofPolicy::Block`] checks for Ctrl-C while waiting for input.
+const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Cleared by Ctrl-C. `ctrlc` only allows one handler per process, so it's
+/// registered once and shared by every call to [`VM::run`].
+static RUNNING: AtomicBool = AtomicBool::new(true);
+static CTRL_C_HANDLER: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// The first two bytes of every save file, "SY".
+pub const SAVE_MAGIC: u16 = 0x5359;
+/// Changed whenever the save file layout changes.
+pub const SAVE_FORMAT_VERSION: u8 = 1;
+/// The oldest save format version that can still be loaded.
+pub const MIN_SAVE_FORMAT_VERSION: u8 = 1;
+
+/// Why [`VM::run`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitReason {
+	/// The program halted, or reached the end of its input.
+	Halt,
+	/// The user pressed Ctrl-C.
+	Interrupt,
+	/// The limit set by [`VM::with_max_steps`] was reached.
+	StepLimit(u64),
+}
+
+/// What makes a [`Breakpoint`] stop the VM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BreakpointKind {
+	/// The next instruction is at this address.
+	Address(usize),
+	/// The next instruction has this opcode.
+	Opcode(u16),
+	/// A register has become equal to `value`.
+	RegisterEquals { index: usize, value: u16 },
+	/// The memory at `addr` has become equal to `value`.
+	MemoryEquals { addr: u16, value: u16 },
+}
+
+/// A breakpoint added with [`VM::add_breakpoint`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+	pub id: usize,
+	pub kind: BreakpointKind,
+	/// How many times it has stopped the VM.
+	pub hits: u64,
+	/// Whether the condition held the last time it was checked, so that
+	/// register and memory breakpoints only stop when it starts to hold.
+	matched: bool,
+}
+
+/// What happened while running, counted once [`VM::enable_stats`] is called.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutionStats {
+	pub instructions_executed: u64,
+	pub memory_reads: u64,
+	pub memory_writes: u64,
+	pub stack_max_depth: usize,
+	/// The time spent in [`VM::run`] and [`VM::run_while`].
+	pub elapsed: Duration,
+}
+
+/// Whether the `jt` or `jf` at `addr` jumped, recorded once
+/// [`VM::enable_branch_trace`] is called.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BranchRecord {
+	pub addr: usize,
+	pub taken: bool,
+}
+
+/// What one step changed, to undo it with [`VM::step_back`]. An instruction
+/// writes at most one register or word of memory, and pushes or pops at most
+/// one value, so only those are kept.
+#[derive(Clone, Debug)]
+struct UndoRecord {
+	pointer: usize,
+	registers: [u16; 8],
+	/// The length of the stack and its top value before the step.
+	stack: (usize, Option<u16>),
+	/// The address written by a `wmem`, and what was written there before.
+	memory: Option<(usize, Option<u16>)>,
+	call_stack: (usize, Option<usize>),
+}
+
+/// The steps [`VM::step_back`] can undo, oldest first.
+#[derive(Clone, Debug)]
+struct History {
+	records: VecDeque<UndoRecord>,
+	cap: usize,
+}
+
+/// How many steps apart [`CycleDetector`] looks at the state.
+const CYCLE_SAMPLE_INTERVAL: u64 = 1000;
+
+/// Finds loops with Brent's algorithm on a hash of the pointer, registers and
+/// stack depth, sampled every [`CYCLE_SAMPLE_INTERVAL`] steps. Memory and the
+/// values on the stack aren't hashed, so a loop that only changes those is
+/// taken for an infinite one.
+#[derive(Clone, Debug, Default)]
+struct CycleDetector {
+	steps: u64,
+	tortoise: Option<u64>,
+	power: u64,
+	lambda: u64,
+}
+
+impl CycleDetector {
+	/// Counts a step, returning the period of the loop if one is found.
+	fn step(&mut self, pointer: usize, data: &Data) -> Option<u64> {
+		self.steps += 1;
+		if !self.steps.is_multiple_of(CYCLE_SAMPLE_INTERVAL) {
+			return None;
+		}
+		let mut hasher = DefaultHasher::new();
+		(pointer, data.registers, data.stack.len()).hash(&mut hasher);
+		let hash = hasher.finish();
+		match self.tortoise {
+			None => {
+				self.tortoise = Some(hash);
+				self.power = 1;
+			}
+			Some(tortoise) => {
+				self.lambda += 1;
+				if tortoise == hash {
+					return Some(self.lambda * CYCLE_SAMPLE_INTERVAL);
+				}
+				if self.lambda == self.power {
+					self.tortoise = Some(hash);
+					self.power *= 2;
+					self.lambda = 0;
+				}
+			}
+		}
+		None
+	}
+}
+
 enum Action {
-	Move(u16),
+	Next(),
 	Jump(u16),
 	Halt(),
+	EndOfInput(),
+}
+
+/// What to do when an `in` instruction finds the input exhausted.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EofPolicy {
+	/// Stop execution, as if a `halt` was executed.
+	#[default]
+	Halt,
+	/// Stop execution with an error.
+	Error,
+	/// Wait for bytes sent through the VM's injector, see
+	/// [`VM::with_injector`], and then retry the `in` instruction. Stops as
+	/// with `Halt` once the injector is closed and empty, and with an error
+	/// when there's no injector, since nothing could ever arrive.
+	Block,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+impl FromStr for EofPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"halt" => Ok(EofPolicy::Halt),
+			"error" => Ok(EofPolicy::Error),
+			"block" => Ok(EofPolicy::Block),
+			_ => Err(format!("Unknown EOF policy \"{}\"!", s)),
+		}
+	}
+}
+
+/// What happened in one [`VM::step`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepInfo {
+	/// Whether the program stopped, by `halt` or at the end of input.
+	pub halted: bool,
+	pub pointer_before: usize,
+	/// The next instruction. The same as `pointer_before` when nothing ran,
+	/// such as at a breakpoint.
+	pub pointer_after: usize,
+	pub opcode: u16,
+	pub mnemonic: &'static str,
+}
+
+/// Why [`VM::step_n`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepOutcome {
+	/// Every step was taken, and the program is still running.
+	Running,
+	/// The program halted, or reached the end of its input.
+	Halted,
+	/// A breakpoint was hit before the instruction at this address.
+	Breakpoint(usize),
+}
+
+#[derive(Clone)]
 pub struct VM<'a> {
 	pub data: Data<'a>,
 	pub pointer: usize,
+	eof_policy: EofPolicy,
+	non_ascii_policy: NonAsciiPolicy,
+	line_ending: LineEnding,
+	newline_mode: NewlineMode,
+	out_format: OutFormat,
+	/// Whether `out` has written a number yet, so the next one is preceded
+	/// by a space.
+	numbers_started: bool,
+	breakpoints: Vec<Breakpoint>,
+	next_breakpoint_id: usize,
+	/// The address a breakpoint stopped at, which is executed by the next
+	/// step instead of stopping again.
+	stopped_at: Option<usize>,
+	call_stack: Vec<usize>,
+	stats: Option<ExecutionStats>,
+	source_map: Option<SourceMap>,
+	buffer_size: Option<usize>,
+	output_buffer: Vec<u8>,
+	cycle_detector: Option<CycleDetector>,
+	branch_trace: Option<Vec<BranchRecord>>,
+	history: Option<History>,
+	coverage: Option<HashSet<usize>>,
+	max_steps: Option<u64>,
+	step_counter: Option<Arc<AtomicU64>>,
+	injector: Option<InputInjector>,
+	/// The address and opcode of the last instruction executed, to explain
+	/// how the pointer ended up outside memory.
+	last_instruction: Option<(usize, u16)>,
+	max_output: Option<u64>,
+	pre_hooks: Vec<PreHook>,
+	post_hooks: Vec<PostHook>,
+	/// Bytes written by runs so far, counted towards `max_output`.
+	output_written: u64,
 }
 
 impl<'a> VM<'a> {
@@ -29,17 +271,224 @@ impl<'a> VM<'a> {
 		Self {
 			data,
 			pointer: 0,
+			eof_policy: EofPolicy::default(),
+			non_ascii_policy: NonAsciiPolicy::default(),
+			line_ending: LineEnding::default(),
+			newline_mode: NewlineMode::default(),
+			out_format: OutFormat::default(),
+			numbers_started: false,
+			breakpoints: Vec::new(),
+			next_breakpoint_id: 0,
+			stopped_at: None,
+			call_stack: Vec::new(),
+			stats: None,
+			source_map: None,
+			buffer_size: None,
+			output_buffer: Vec::new(),
+			cycle_detector: None,
+			branch_trace: None,
+			history: None,
+			coverage: None,
+			max_steps: None,
+			step_counter: None,
+			injector: None,
+			last_instruction: None,
+			max_output: None,
+			pre_hooks: Vec::new(),
+			post_hooks: Vec::new(),
+			output_written: 0,
+		}
+	}
+
+	/// Names the source line of the failing instruction in error messages.
+	pub fn with_source_map(mut self, source_map: SourceMap) -> Self {
+		self.source_map = Some(source_map);
+		self
+	}
+
+	/// Holds up to `buf_size` bytes of output before writing them, instead of
+	/// writing every `out` and flushing every line. What's held is written
+	/// before reading input, when [`VM::run`] stops, and by
+	/// [`VM::flush_output`].
+	pub fn with_buffered_output(mut self, buf_size: usize) -> Self {
+		self.buffer_size = Some(buf_size.max(1));
+		self.output_buffer = Vec::with_capacity(buf_size);
+		self
+	}
+
+	/// Makes running fail with [`RuntimeError::InfiniteLoop`] when the
+	/// program seems to be stuck in a loop. The reported period is a multiple
+	/// of the loop's, and some loops that only change memory are mistaken for
+	/// infinite ones.
+	pub fn with_cycle_detection(mut self, enabled: bool) -> Self {
+		self.cycle_detector = if enabled {
+			Some(CycleDetector::default())
+		} else {
+			None
+		};
+		self
+	}
+
+	/// Makes each call to [`VM::run`] stop after at most `max_steps` steps.
+	pub fn with_max_steps(mut self, max_steps: u64) -> Self {
+		self.max_steps = Some(max_steps);
+		self
+	}
+
+	/// Makes [`VM::run`] fail once the program has written `max_output`
+	/// bytes, counted over every run.
+	pub fn with_max_output(mut self, max_output: u64) -> Self {
+		self.max_output = Some(max_output);
+		self
+	}
+
+	/// Makes [`VM::run`] add one to `counter` for every step, so that another
+	/// thread can report the progress.
+	pub fn with_step_counter(mut self, counter: Arc<AtomicU64>) -> Self {
+		self.step_counter = Some(counter);
+		self
+	}
+
+	/// Returns an [`InputInjector`] whose bytes `in` reads ahead of the input
+	/// given to [`VM::step`], such as for a debugging tool to send a command.
+	/// Clones of the VM read from the same queue.
+	pub fn with_injector(mut self) -> (Self, InputInjector) {
+		let injector = InputInjector::default();
+		self.injector = Some(injector.clone());
+		(self, injector)
+	}
+
+	/// Writes the output held by [`VM::with_buffered_output`] to `output`.
+	pub fn flush_output<O: Write>(&mut self, output: &mut O) -> Result<(), String> {
+		OutputBuffer::new(&mut self.output_buffer, 1, output)
+			.drain()
+			.map_err(|e| format!("Could not flush output. {}", e))
+	}
+
+	pub fn with_eof_policy(mut self, policy: EofPolicy) -> Self {
+		self.eof_policy = policy;
+		self
+	}
+
+	/// Sets the line endings of the input, which `in` always reads as LF.
+	pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+		self.line_ending = line_ending;
+		self
+	}
+
+	/// Sets how `in` and `out` translate newlines. [`NewlineMode::Raw`] and
+	/// [`NewlineMode::NormalizeLf`] read the input with their own line ending,
+	/// in place of the one set by [`VM::with_line_ending`], while
+	/// [`NewlineMode::TranslateLfToCrLf`] only changes the output.
+	pub fn with_newline_mode(mut self, mode: NewlineMode) -> Self {
+		if let Some(line_ending) = mode.line_ending() {
+			self.line_ending = line_ending;
 		}
+		self.newline_mode = mode;
+		self
+	}
+
+	/// Sets whether `out` writes characters or their codes as numbers.
+	pub fn with_out_format(mut self, format: OutFormat) -> Self {
+		self.out_format = format;
+		self
+	}
+
+	/// Sets how [`VM::run_stdio`] treats non-ASCII input.
+	pub fn with_non_ascii_policy(mut self, policy: NonAsciiPolicy) -> Self {
+		self.non_ascii_policy = policy;
+		self
 	}
 
+	/// Saves the state to a buffer, see [`VM::save_to_writer`].
 	pub fn save(&self) -> Result<Vec<u8>, String> {
-		bincode::serialize(self).map_err(|e| format!("Could not format save file. {}", e))
+		let mut save = Vec::new();
+		self.save_to_writer(&mut save)?;
+		Ok(save)
 	}
 
+	/// Loads a state saved with [`VM::save`] onto `memory`, the binary the
+	/// save was made from.
 	pub fn load(memory: &'a [u16], save: &[u8]) -> Result<Self, String> {
-		let mut vm: Self =
-			bincode::deserialize(save).map_err(|e| format!("Could not read save file. {}", e))?;
-		vm.data.memory = memory;
+		Ok(Self::load_from_reader(memory, &mut &save[..])?)
+	}
+
+	/// Writes everything needed to resume execution, except for the binary
+	/// itself. All numbers are little endian, laid out as:
+	///
+	/// ```text
+	/// magic:     u16 = 0x5359 ("SY")
+	/// version:   u8 = SAVE_FORMAT_VERSION
+	/// pointer:   u16
+	/// registers: u16 * 8
+	/// n_changes: u32
+	/// changes:   (addr: u16, value: u16) * n_changes, sorted by addr
+	/// stack_len: u32
+	/// stack:     u16 * stack_len, bottom first
+	/// ```
+	pub fn save_to_writer<W: Write>(&self, w: &mut W) -> Result<(), RuntimeError> {
+		let pointer = u16::try_from(self.pointer).map_err(|_| {
+			RuntimeError::InvalidSave(format!("Pointer {} doesn't fit a save.", self.pointer))
+		})?;
+		let mut changes = self.data.memory_changes.iter().collect::<Vec<_>>();
+		changes.sort();
+
+		let mut bytes = SAVE_MAGIC.to_le_bytes().to_vec();
+		bytes.push(SAVE_FORMAT_VERSION);
+		bytes.extend(&pointer.to_le_bytes());
+		for register in self.data.registers.iter() {
+			bytes.extend(&register.to_le_bytes());
+		}
+		bytes.extend(&(changes.len() as u32).to_le_bytes());
+		for (&address, value) in changes {
+			bytes.extend(&(address as u16).to_le_bytes());
+			bytes.extend(&value.to_le_bytes());
+		}
+		bytes.extend(&(self.data.stack.len() as u32).to_le_bytes());
+		for value in self.data.stack.iter() {
+			bytes.extend(&value.to_le_bytes());
+		}
+
+		w.write_all(&bytes)
+			.and_then(|_| w.flush())
+			.map_err(|e| RuntimeError::Io(format!("Could not write save file. {}", e)))
+	}
+
+	/// Reads a state written by [`VM::save_to_writer`] onto `memory`.
+	pub fn load_from_reader<R: Read>(memory: &'a [u16], r: &mut R) -> Result<VM<'a>, RuntimeError> {
+		let mut reader = SaveReader(r);
+		let magic = reader.u16()?;
+		if magic != SAVE_MAGIC {
+			return Err(RuntimeError::InvalidSave(format!(
+				"It starts with {:#06x} instead of {:#06x}.",
+				magic, SAVE_MAGIC
+			)));
+		}
+		let version = reader.u8()?;
+		if !(MIN_SAVE_FORMAT_VERSION..=SAVE_FORMAT_VERSION).contains(&version) {
+			return Err(RuntimeError::IncompatibleSaveVersion {
+				expected: SAVE_FORMAT_VERSION,
+				found: version,
+			});
+		}
+
+		let pointer = reader.u16()? as usize;
+		let mut registers = [0; 8];
+		for register in registers.iter_mut() {
+			*register = reader.u16()?;
+		}
+		let mut changes = HashMap::new();
+		for _ in 0..reader.u32()? {
+			let address = reader.u16()? as usize;
+			let value = reader.u16()?;
+			changes.insert(address, value);
+		}
+		let mut stack = Vec::new();
+		for _ in 0..reader.u32()? {
+			stack.push(reader.u16()?);
+		}
+		let mut vm = VM::new(Data::from_state(memory, registers, stack, changes));
+		vm.pointer = pointer;
 		Ok(vm)
 	}
 
@@ -47,65 +496,624 @@ impl<'a> VM<'a> {
 		&mut self,
 		input: &mut I,
 		output: &mut O,
-	) -> Result<bool, String> {
+	) -> Result<StepInfo, String> {
 		if self.pointer >= self.data.length_memory() {
-			return Err(format!("Out of range {}!", self.pointer));
+			return Err(match self.last_instruction {
+				Some((addr, opcode)) => format!(
+					"Ran past end of memory at {}; last instruction {}@{}",
+					self.pointer,
+					opcode::mnemonic(opcode).unwrap_or("?"),
+					addr
+				),
+				None => format!("Ran past end of memory at {}!", self.pointer),
+			});
 		}
 
-		let handler = get_handler(self.data.get_number(self.pointer).unwrap());
-		match handler(&mut self.data, self.pointer, input, output) {
-			Ok(Action::Move(m)) => self.pointer += m as usize,
+		let pointer = self.pointer;
+		self.data.set_current_ip(pointer);
+		let opcode = self
+			.data
+			.get_number(self.pointer)
+			.map_err(|e| format!("{}:\n\t{}", self.error_location(), e))?;
+		let info = |halted, pointer_after| StepInfo {
+			halted,
+			pointer_before: pointer,
+			pointer_after,
+			opcode,
+			mnemonic: opcode::mnemonic(opcode).unwrap_or("?"),
+		};
+		if self.stopped_at.take() != Some(pointer) && self.check_breakpoints(opcode) {
+			self.stopped_at = Some(pointer);
+			return Ok(info(false, pointer));
+		}
+		for hook in &self.pre_hooks {
+			hook(pointer, opcode);
+		}
+		let undo = self.history.as_ref().map(|_| self.undo_record(opcode));
+		let input = InjectedInput::new(input, self.injector.as_ref());
+		let mut input = LineEndingInput::new(input, self.line_ending);
+		let translate = self.newline_mode == NewlineMode::TranslateLfToCrLf;
+		let result = match self.buffer_size {
+			Some(capacity) => {
+				let mut output = OutputBuffer::new(&mut self.output_buffer, capacity, output);
+				// Shows what was written before an `in` waits for input.
+				if opcode == 20 {
+					output
+						.drain()
+						.map_err(|e| format!("Could not flush output. {}", e))?;
+				}
+				let output = CrLfOutput::new(output, translate);
+				let mut output =
+					NumericOutput::new(output, self.out_format, &mut self.numbers_started);
+				get_handler(opcode)(&mut self.data, pointer, &mut input, &mut output)
+			}
+			None => {
+				let output = CrLfOutput::new(output, translate);
+				let mut output =
+					NumericOutput::new(output, self.out_format, &mut self.numbers_started);
+				get_handler(opcode)(&mut self.data, pointer, &mut input, &mut output)
+			}
+		};
+		if let (Some(stats), Ok(action)) = (&mut self.stats, &result) {
+			if !matches!(action, Action::EndOfInput()) {
+				stats.instructions_executed += 1;
+				stats.stack_max_depth = stats.stack_max_depth.max(self.data.stack.len());
+			}
+		}
+		if let Ok(Action::Next() | Action::Jump(_) | Action::Halt()) = result {
+			for hook in &self.post_hooks {
+				hook(pointer, opcode, &self.data);
+			}
+		}
+		if let (Some(history), Some(undo), Ok(Action::Next() | Action::Jump(_))) =
+			(&mut self.history, undo, &result)
+		{
+			if history.records.len() == history.cap {
+				history.records.pop_front();
+			}
+			if history.cap > 0 {
+				history.records.push_back(undo);
+			}
+		}
+		if let (Some(coverage), Ok(_)) = (&mut self.coverage, &result) {
+			coverage.insert(pointer);
+		}
+		if let (Some(trace), 7 | 8, Ok(action)) = (&mut self.branch_trace, opcode, &result) {
+			trace.push(BranchRecord {
+				addr: pointer,
+				taken: matches!(action, Action::Jump(_)),
+			});
+		}
+		if let Ok(Action::Next() | Action::Jump(_)) = result {
+			self.last_instruction = Some((pointer, opcode));
+		}
+		match result {
+			Ok(Action::Next()) => self.pointer += opcode::size(opcode).unwrap_or(1),
 			Ok(Action::Jump(j)) => self.pointer = j as usize,
-			Ok(Action::Halt()) => return Ok(false),
+			Ok(Action::Halt()) => return Ok(info(true, pointer)),
+			Ok(Action::EndOfInput()) => match self.eof_policy {
+				EofPolicy::Halt => return Ok(info(true, pointer)),
+				EofPolicy::Error => {
+					return Err(format!(
+						"{}:\n\tReached end of input!",
+						self.error_location()
+					))
+				}
+				EofPolicy::Block => match &self.injector {
+					Some(injector) => {
+						// Wakes up now and then to see if Ctrl-C was pressed.
+						while !injector.wait(INTERRUPT_CHECK_INTERVAL) {
+							if injector.is_closed() {
+								return Ok(info(true, pointer));
+							}
+							if !RUNNING.load(Ordering::SeqCst) {
+								break;
+							}
+						}
+					}
+					None => {
+						return Err(format!(
+							"{}:\n\tReached end of input, with no injector to wait on!",
+							self.error_location()
+						))
+					}
+				},
+			},
 			Err(err) => {
-				return Err(format!("Error at {}:\n\t{}", self.pointer, err));
+				return Err(format!("{}:\n\t{}", self.error_location(), err));
 			}
 		};
+		let (pointer_now, data) = (self.pointer, &self.data);
+		if let Some(period) = self
+			.cycle_detector
+			.as_mut()
+			.and_then(|detector| detector.step(pointer_now, data))
+		{
+			return Err(format!(
+				"{}:\n\t{}",
+				self.error_location(),
+				RuntimeError::InfiniteLoop {
+					period,
+				}
+			));
+		}
+		match opcode {
+			17 => self.call_stack.push(pointer + 2),
+			18 => {
+				self.call_stack.pop();
+			}
+			_ => {}
+		}
+
+		Ok(info(false, self.pointer))
+	}
+
+	/// Where the current instruction is, for the start of an error message.
+	fn error_location(&self) -> String {
+		match self
+			.source_map
+			.as_ref()
+			.and_then(|map| map.location(self.pointer))
+		{
+			Some(location) => format!("Error at {} (address {:#06x})", location, self.pointer),
+			None => format!("Error at {}", self.pointer),
+		}
+	}
+
+	/// Calls `hook` with the pointer and opcode before every instruction,
+	/// after any earlier hooks.
+	pub fn add_pre_hook<F: Fn(usize, u16) + 'static>(&mut self, hook: F) {
+		self.pre_hooks.push(Rc::new(hook));
+	}
+
+	/// Calls `hook` with the pointer, opcode and data after every instruction
+	/// that runs without error, after any earlier hooks.
+	pub fn add_post_hook<F: Fn(usize, u16, &Data) + 'static>(&mut self, hook: F) {
+		self.post_hooks.push(Rc::new(hook));
+	}
+
+	pub fn clear_hooks(&mut self) {
+		self.pre_hooks.clear();
+		self.post_hooks.clear();
+	}
+
+	/// Starts recording the address of every instruction executed.
+	pub fn enable_coverage(&mut self) {
+		self.coverage.get_or_insert_with(HashSet::new);
+	}
+
+	/// The addresses of the instructions executed since
+	/// [`VM::enable_coverage`], or `None` if it hasn't been called.
+	pub fn coverage(&self) -> Option<&HashSet<usize>> {
+		self.coverage.as_ref()
+	}
+
+	/// Starts recording whether each `jt` and `jf` jumps.
+	pub fn enable_branch_trace(&mut self) {
+		self.branch_trace.get_or_insert_with(Vec::new);
+	}
+
+	pub fn branch_trace(&self) -> &[BranchRecord] {
+		self.branch_trace.as_deref().unwrap_or(&[])
+	}
+
+	pub fn clear_branch_trace(&mut self) {
+		if let Some(trace) = &mut self.branch_trace {
+			trace.clear();
+		}
+	}
+
+	/// Starts recording what each step changes, keeping the last `cap` steps
+	/// for [`VM::step_back`].
+	pub fn enable_history(&mut self, cap: usize) {
+		self.history = Some(History {
+			records: VecDeque::with_capacity(cap),
+			cap,
+		});
+	}
 
+	/// Undoes the last step recorded since [`VM::enable_history`], returning
+	/// whether there was one. Output that was written stays written.
+	pub fn step_back(&mut self) -> Result<bool, String> {
+		let record = match self.history.as_mut().and_then(|h| h.records.pop_back()) {
+			Some(record) => record,
+			None => return Ok(false),
+		};
+		self.pointer = record.pointer;
+		self.data.registers = record.registers;
+		let (length, top) = record.stack;
+		self.data.stack.truncate(length);
+		if let Some(top) = top.filter(|_| self.data.stack.len() < length) {
+			self.data.stack.push(top);
+		}
+		match record.memory {
+			Some((address, Some(value))) => {
+				self.data.memory_changes.insert(address, value);
+			}
+			Some((address, None)) => {
+				self.data.memory_changes.remove(&address);
+			}
+			None => {}
+		}
+		let (length, top) = record.call_stack;
+		self.call_stack.truncate(length);
+		if let Some(top) = top.filter(|_| self.call_stack.len() < length) {
+			self.call_stack.push(top);
+		}
+		self.stopped_at = None;
 		Ok(true)
 	}
 
-	pub fn run<I: Read, O: Write>(&mut self, input: &mut I, output: &mut O) -> Result<(), String> {
-		let running = Arc::new(AtomicBool::new(true));
-		let r = running.clone();
+	/// What executing `opcode` at the pointer could change.
+	fn undo_record(&self, opcode: u16) -> UndoRecord {
+		let memory = match opcode {
+			16 => self.data.get_number(self.pointer + 1).ok().map(|address| {
+				let address = address as usize;
+				(address, self.data.memory_changes.get(&address).copied())
+			}),
+			_ => None,
+		};
+		UndoRecord {
+			pointer: self.pointer,
+			registers: self.data.registers,
+			stack: (self.data.stack.len(), self.data.stack.last().copied()),
+			memory,
+			call_stack: (self.call_stack.len(), self.call_stack.last().copied()),
+		}
+	}
+
+	/// Checks every breakpoint before executing `opcode`, counting a hit for
+	/// each one that triggers. Returns whether any did.
+	fn check_breakpoints(&mut self, opcode: u16) -> bool {
+		let mut triggered = false;
+		for breakpoint in self.breakpoints.iter_mut() {
+			let matches = match breakpoint.kind {
+				BreakpointKind::Address(address) => address == self.pointer,
+				BreakpointKind::Opcode(op) => op == opcode,
+				BreakpointKind::RegisterEquals {
+					index,
+					value,
+				} => self.data.get_register(index) == Ok(value),
+				BreakpointKind::MemoryEquals {
+					addr,
+					value,
+				} => self.data.fetch(addr) == Ok(value),
+			};
+			let edge_triggered = matches!(
+				breakpoint.kind,
+				BreakpointKind::RegisterEquals { .. } | BreakpointKind::MemoryEquals { .. }
+			);
+			if matches && !(edge_triggered && breakpoint.matched) {
+				breakpoint.hits += 1;
+				triggered = true;
+			}
+			breakpoint.matched = matches;
+		}
+		triggered
+	}
+
+	/// Starts counting [`ExecutionStats`], from zero.
+	pub fn enable_stats(&mut self) {
+		self.stats = Some(ExecutionStats {
+			stack_max_depth: self.data.stack.len(),
+			..ExecutionStats::default()
+		});
+		self.data.memory_reads = 0;
+		self.data.memory_writes = 0;
+	}
+
+	/// What has happened since [`VM::enable_stats`], or all zeros if it
+	/// hasn't been called.
+	pub fn statistics(&self) -> ExecutionStats {
+		match &self.stats {
+			Some(stats) => ExecutionStats {
+				memory_reads: self.data.memory_reads,
+				memory_writes: self.data.memory_writes,
+				..stats.clone()
+			},
+			None => ExecutionStats::default(),
+		}
+	}
+
+	/// Makes `addr` the next instruction to execute. Returns a warning, but
+	/// moves anyway, if `addr` isn't where an instruction starts when
+	/// disassembling the binary from the first address.
+	pub fn set_pointer(&mut self, addr: u16) -> Result<Option<String>, String> {
+		let addr = addr as usize;
+		if addr >= self.data.length_memory() {
+			return Err(format!("Can't jump to {}, it's outside of memory!", addr));
+		}
+		let warning = if addr < self.data.memory.len()
+			&& !Disassembler::new(self.data.memory)
+				.take_while(|&(a, _)| a <= addr)
+				.any(|(a, _)| a == addr)
+		{
+			Some(format!("{} isn't the start of an instruction.", addr))
+		} else {
+			None
+		};
+		self.pointer = addr;
+		self.stopped_at = None;
+		Ok(warning)
+	}
+
+	/// The return addresses of the calls that haven't returned yet, innermost
+	/// last. This isn't saved, so it starts out empty after loading.
+	pub fn call_stack(&self) -> &[usize] {
+		&self.call_stack
+	}
+
+	/// Takes a single step, except that a `call` is run until it returns to
+	/// the instruction after it. Returns whether the program is still
+	/// running.
+	pub fn step_over<I: Read, O: Write>(
+		&mut self,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<bool, String> {
+		let calling = self.data.get_number(self.pointer) == Ok(17);
+		if self.step(input, output)?.halted {
+			return Ok(false);
+		}
+		if calling && !self.at_breakpoint() {
+			self.step_out(input, output)
+		} else {
+			Ok(true)
+		}
+	}
+
+	/// Runs until the current function returns, the program halts, or a
+	/// breakpoint is hit. Returns whether the program is still running.
+	pub fn step_out<I: Read, O: Write>(
+		&mut self,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<bool, String> {
+		let depth = self.call_stack.len();
+		loop {
+			let returning =
+				self.call_stack.len() == depth && self.data.get_number(self.pointer) == Ok(18);
+			if self.step(input, output)?.halted {
+				return Ok(false);
+			}
+			if self.at_breakpoint() {
+				return Ok(true);
+			}
+			if returning {
+				return Ok(true);
+			}
+		}
+	}
 
-		ctrlc::set_handler(move || r.store(false, Ordering::SeqCst))
-			.map_err(|_| "Could not set Ctrl-C handler!".to_string())?;
+	pub fn run<I: Read, O: Write>(
+		&mut self,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<ExitReason, String> {
+		CTRL_C_HANDLER
+			.get_or_init(|| {
+				ctrlc::set_handler(|| RUNNING.store(false, Ordering::SeqCst))
+					.map_err(|_| "Could not set Ctrl-C handler!".to_string())
+			})
+			.clone()?;
+		RUNNING.store(true, Ordering::SeqCst);
+		self.run_while(&RUNNING, input, output)
+	}
 
-		while self.step(input, output)? && running.load(Ordering::SeqCst) {}
+	/// Runs until the program halts or `running` is cleared.
+	pub fn run_while<I: Read, O: Write>(
+		&mut self,
+		running: &AtomicBool,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<ExitReason, String> {
+		let start = Instant::now();
+		let result = match self.max_output {
+			Some(limit) => {
+				let mut limited = OutputLimit::new(output, self.output_written, limit);
+				let result = self.run_and_flush(running, input, &mut limited);
+				self.output_written = limited.written();
+				if limited.exceeded() {
+					Err(format!("Output limit of {} bytes exceeded", limit))
+				} else {
+					result
+				}
+			}
+			None => self.run_and_flush(running, input, output),
+		};
+		if let Some(stats) = &mut self.stats {
+			stats.elapsed += start.elapsed();
+		}
+		result
+	}
 
-		Ok(())
+	fn run_and_flush<I: Read, O: Write>(
+		&mut self,
+		running: &AtomicBool,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<ExitReason, String> {
+		let result = self.run_until_stopped(running, input, output);
+		let flushed = self.flush_output(output);
+		result.and_then(|reason| flushed.map(|_| reason))
+	}
+
+	fn run_until_stopped<I: Read, O: Write>(
+		&mut self,
+		running: &AtomicBool,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<ExitReason, String> {
+		let mut steps = 0;
+		while running.load(Ordering::SeqCst) {
+			if let Some(max_steps) = self.max_steps.filter(|&m| m == steps) {
+				return Ok(ExitReason::StepLimit(max_steps));
+			}
+			let info = self.step(input, output)?;
+			if let Some(counter) = &self.step_counter {
+				counter.fetch_add(1, Ordering::Relaxed);
+			}
+			if info.halted {
+				return Ok(ExitReason::Halt);
+			}
+			steps += 1;
+		}
+		Ok(ExitReason::Interrupt)
+	}
+
+	/// Takes at most `n` steps, returning whether the program is still running.
+	pub fn run_for_n_steps<I: Read, O: Write>(
+		&mut self,
+		n: u64,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<bool, String> {
+		for _ in 0..n {
+			if self.step(input, output)?.halted {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	/// Takes at most `n` steps like [`VM::run_for_n_steps`], but stops early
+	/// when a breakpoint is hit, before executing that instruction.
+	pub fn step_n<I: Read, O: Write>(
+		&mut self,
+		n: u64,
+		input: &mut I,
+		output: &mut O,
+	) -> Result<StepOutcome, String> {
+		for _ in 0..n {
+			if self.step(input, output)?.halted {
+				return Ok(StepOutcome::Halted);
+			}
+			if self.at_breakpoint() {
+				return Ok(StepOutcome::Breakpoint(self.pointer));
+			}
+		}
+		Ok(StepOutcome::Running)
+	}
+
+	/// Runs a script of debugger commands, one per line, on a new VM with
+	/// `memory`, and returns what they printed. Blank lines and lines
+	/// starting with `#` are skipped. The program reads from `vm_input` and
+	/// writes to `vm_output`.
+	pub fn run_script<I: Read, O: Write>(
+		memory: &[u16],
+		commands: &str,
+		mut vm_input: I,
+		mut vm_output: O,
+	) -> Result<String, String> {
+		let mut vm = VM::new(Data::new(memory));
+		let mut log = String::new();
+		for (i, line) in commands.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			line.parse::<Command>()
+				.and_then(|command| {
+					command.execute(&mut vm, &mut vm_input, &mut vm_output, &mut log)
+				})
+				.map_err(|e| format!("Error on line {} of the script.\n\t{}", i + 1, e))?;
+		}
+		Ok(log)
+	}
+
+	/// Adds a breakpoint, returning its id.
+	pub fn add_breakpoint(&mut self, kind: BreakpointKind) -> usize {
+		let id = self.next_breakpoint_id;
+		self.next_breakpoint_id += 1;
+		self.breakpoints.push(Breakpoint {
+			id,
+			kind,
+			hits: 0,
+			matched: false,
+		});
+		id
+	}
+
+	pub fn remove_breakpoint(&mut self, id: usize) {
+		self.breakpoints.retain(|b| b.id != id);
+	}
+
+	pub fn list_breakpoints(&self) -> &[Breakpoint] {
+		&self.breakpoints
+	}
+
+	pub fn clear_breakpoints(&mut self) {
+		self.breakpoints.clear();
+		self.stopped_at = None;
+	}
+
+	/// Whether the last step stopped at a breakpoint instead of executing the
+	/// next instruction.
+	pub fn at_breakpoint(&self) -> bool {
+		self.stopped_at == Some(self.pointer)
+	}
+
+	/// Runs against the standard input and output. When the `lineeditor`
+	/// feature is enabled and stdin is a terminal, input is read through a
+	/// line editor with history, otherwise it is read byte by byte. Either
+	/// way, non-ASCII input is handled by the VM's [`NonAsciiPolicy`].
+	pub fn run_stdio(&mut self) -> Result<ExitReason, String> {
+		self.run_stdio_to(&mut io::stdout())
+	}
+
+	/// Like [`VM::run_stdio`], but writes the output to `output` instead of
+	/// the standard output.
+	pub fn run_stdio_to<O: Write>(&mut self, output: &mut O) -> Result<ExitReason, String> {
+		self.run(&mut input::stdin(self.non_ascii_policy)?, output)
 	}
 }
 
-fn get_handler<I: Read, O: Write>(opcode: u16) -> Handler<I, O> {
-	match opcode {
-		0 => halt,
-		1 => set,
-		2 => push,
-		3 => pop,
-		4 => eq,
-		5 => gt,
-		6 => jmp,
-		7 => jt,
-		8 => jf,
-		9 => add,
-		10 => mul,
-		11 => mod_op,
-		12 => and,
-		13 => or,
-		14 => not,
-		15 => rmem,
-		16 => wmem,
-		17 => call,
-		18 => ret,
-		19 => out,
-		20 => in_op,
-		21 => noop,
-		_ => unknown,
+/// Reads the little endian numbers of a save file.
+struct SaveReader<'r, R>(&'r mut R);
+
+impl<'r, R: Read> SaveReader<'r, R> {
+	fn bytes<const N: usize>(&mut self) -> Result<[u8; N], RuntimeError> {
+		let mut bytes = [0; N];
+		self.0.read_exact(&mut bytes).map_err(|e| match e.kind() {
+			io::ErrorKind::UnexpectedEof => {
+				RuntimeError::InvalidSave("It ended too early.".to_string())
+			}
+			_ => RuntimeError::Io(format!("Could not read save file. {}", e)),
+		})?;
+		Ok(bytes)
+	}
+
+	fn u8(&mut self) -> Result<u8, RuntimeError> {
+		Ok(self.bytes::<1>()?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, RuntimeError> {
+		Ok(u16::from_le_bytes(self.bytes()?))
+	}
+
+	fn u32(&mut self) -> Result<u32, RuntimeError> {
+		Ok(u32::from_le_bytes(self.bytes()?))
 	}
 }
 
+fn get_handler<I: Read, O: Write>(opcode: u16) -> Handler<I, O> {
+	Handlers::<I, O>::TABLE
+		.get(opcode as usize)
+		.copied()
+		.unwrap_or(unknown)
+}
+
+/// Handlers indexed by opcode. A generic `static` isn't allowed, so the table
+/// is an associated constant instead.
+struct Handlers<I, O>(std::marker::PhantomData<(I, O)>);
+
+impl<I: Read, O: Write> Handlers<I, O> {
+	const TABLE: [Handler<I, O>; 22] = [
+		halt, set, push, pop, eq, gt, jmp, jt, jf, add, mult, mod_op, and, or, not, rmem, wmem,
+		call, ret, out, in_op, noop,
+	];
+}
+
 fn halt<I: Read, O: Write>(_: &mut Data, _: usize, _: &mut I, _: &mut O) -> Result<Action, String> {
 	Ok(Action::Halt())
 }
@@ -117,7 +1125,7 @@ fn set<I: Read, O: Write>(
 	_: &mut O,
 ) -> Result<Action, String> {
 	data.set_number(i + 1, data.get_number(i + 2)?)?;
-	Ok(Action::Move(3))
+	Ok(Action::Next())
 }
 
 fn push<I: Read, O: Write>(
@@ -127,7 +1135,7 @@ fn push<I: Read, O: Write>(
 	_: &mut O,
 ) -> Result<Action, String> {
 	data.push_stack(data.get_number(i + 1)?);
-	Ok(Action::Move(2))
+	Ok(Action::Next())
 }
 
 fn pop<I: Read, O: Write>(
@@ -138,7 +1146,7 @@ fn pop<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = data.pop_stack()?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(2))
+	Ok(Action::Next())
 }
 
 fn eq<I: Read, O: Write>(
@@ -149,7 +1157,7 @@ fn eq<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = (data.get_number(i + 2)? == data.get_number(i + 3)?) as u16;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn gt<I: Read, O: Write>(
@@ -160,7 +1168,7 @@ fn gt<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = (data.get_number(i + 2)? > data.get_number(i + 3)?) as u16;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn jmp<I: Read, O: Write>(
@@ -181,7 +1189,7 @@ fn jt<I: Read, O: Write>(
 	if data.get_number(i + 1)? != 0 {
 		Ok(Action::Jump(data.get_number(i + 2)?))
 	} else {
-		Ok(Action::Move(3))
+		Ok(Action::Next())
 	}
 }
 
@@ -194,7 +1202,7 @@ fn jf<I: Read, O: Write>(
 	if data.get_number(i + 1)? == 0 {
 		Ok(Action::Jump(data.get_number(i + 2)?))
 	} else {
-		Ok(Action::Move(3))
+		Ok(Action::Next())
 	}
 }
 
@@ -204,12 +1212,12 @@ fn add<I: Read, O: Write>(
 	_: &mut I,
 	_: &mut O,
 ) -> Result<Action, String> {
-	let value = (data.get_number(i + 2)? + data.get_number(i + 3)?) % 32768;
+	let value = ((data.get_number(i + 2)? as u32 + data.get_number(i + 3)? as u32) % 32768) as u16;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
-fn mul<I: Read, O: Write>(
+fn mult<I: Read, O: Write>(
 	data: &mut Data,
 	i: usize,
 	_: &mut I,
@@ -218,7 +1226,7 @@ fn mul<I: Read, O: Write>(
 	let value =
 		(((data.get_number(i + 2)? as u64) * (data.get_number(i + 3)? as u64)) % 32768) as u16;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn mod_op<I: Read, O: Write>(
@@ -227,9 +1235,12 @@ fn mod_op<I: Read, O: Write>(
 	_: &mut I,
 	_: &mut O,
 ) -> Result<Action, String> {
-	let value = data.get_number(i + 2)? % data.get_number(i + 3)?;
+	let value = data
+		.get_number(i + 2)?
+		.checked_rem(data.get_number(i + 3)?)
+		.ok_or(RuntimeError::ModByZero)?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn and<I: Read, O: Write>(
@@ -240,7 +1251,7 @@ fn and<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = data.get_number(i + 2)? & data.get_number(i + 3)?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn or<I: Read, O: Write>(
@@ -251,7 +1262,7 @@ fn or<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = data.get_number(i + 2)? | data.get_number(i + 3)?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(4))
+	Ok(Action::Next())
 }
 
 fn not<I: Read, O: Write>(
@@ -262,7 +1273,7 @@ fn not<I: Read, O: Write>(
 ) -> Result<Action, String> {
 	let value = 0x7FFF ^ data.get_number(i + 2)?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(3))
+	Ok(Action::Next())
 }
 
 fn rmem<I: Read, O: Write>(
@@ -274,7 +1285,7 @@ fn rmem<I: Read, O: Write>(
 	let address = data.get_number(i + 2)?;
 	let value = data.read_memory(address)?;
 	data.set_number(i + 1, value)?;
-	Ok(Action::Move(3))
+	Ok(Action::Next())
 }
 
 fn wmem<I: Read, O: Write>(
@@ -286,7 +1297,7 @@ fn wmem<I: Read, O: Write>(
 	let address = data.get_number(i + 1)?;
 	let value = data.get_number(i + 2)?;
 	data.write_memory(address, value)?;
-	Ok(Action::Move(3))
+	Ok(Action::Next())
 }
 
 fn call<I: Read, O: Write>(
@@ -295,8 +1306,14 @@ fn call<I: Read, O: Write>(
 	_: &mut I,
 	_: &mut O,
 ) -> Result<Action, String> {
-	let next_addr = (i + 2) as u16;
-	data.push_stack(next_addr);
+	let next_addr = i + 2;
+	if next_addr > 0x7FFF {
+		return Err(format!(
+			"Return address {} is outside of memory!",
+			next_addr
+		));
+	}
+	data.push_stack(next_addr as u16);
 	Ok(Action::Jump(data.get_number(i + 1)?))
 }
 
@@ -324,7 +1341,14 @@ fn out<I: Read, O: Write>(
 		Ok(str) => {
 			write!(output, "{}", str)
 				.or_else(|_| Err(format!("Could not write {} to output!", str)))?;
-			Ok(Action::Move(2))
+			// Flushing every character is slow, but buffered output should still
+			// show up a line at a time.
+			if ascii == 10 {
+				output
+					.flush()
+					.map_err(|e| format!("Could not flush output. {}", e))?;
+			}
+			Ok(Action::Next())
 		}
 		Err(_) => Err(format!("Could not encode {} as a character!", ascii)),
 	}
@@ -334,25 +1358,21 @@ fn in_op<I: Read, O: Write>(
 	data: &mut Data,
 	i: usize,
 	input: &mut I,
-	output: &mut O,
+	_: &mut O,
 ) -> Result<Action, String> {
 	let mut buf = [0];
 	match input.read(&mut buf) {
 		Ok(1) => {
-			if buf[0] == 13 {
-				in_op(data, i, input, output)
-			} else {
-				data.set_number(i + 1, buf[0] as u16)?;
-				Ok(Action::Move(2))
-			}
+			data.set_number(i + 1, buf[0] as u16)?;
+			Ok(Action::Next())
 		}
-		Ok(0) => Ok(Action::Halt()),
+		Ok(0) => Ok(Action::EndOfInput()),
 		_ => Err("Could not read from input!".to_string()),
 	}
 }
 
 fn noop<I: Read, O: Write>(_: &mut Data, _: usize, _: &mut I, _: &mut O) -> Result<Action, String> {
-	Ok(Action::Move(1))
+	Ok(Action::Next())
 }
 
 fn unknown<I: Read, O: Write>(
@@ -361,16 +1381,24 @@ fn unknown<I: Read, O: Write>(
 	_: &mut I,
 	_: &mut O,
 ) -> Result<Action, String> {
-	Err(format!("Unknown opcode {}!", data.get_number(i)?))
+	Err(RuntimeError::UnknownOpcode(data.get_number(i)?).into())
 }
 
 #[cfg(test)]
 mod tests {
-	use std::io::{empty, sink};
+	use std::{
+		cell::RefCell,
+		io::{empty, sink},
+		rc::Rc,
+		thread,
+	};
 
-	use super::{super::data::Data, *};
+	use super::{
+		super::data::{AccessKind, Data, MemoryAccess},
+		*,
+	};
 
-	const MEMORY: &'static [u16] = &[21, 19, 77, 0];
+	const MEMORY: &[u16] = &[21, 19, 77, 0];
 
 	fn create_vm() -> VM<'static> {
 		VM::new(Data::new(MEMORY))
@@ -386,7 +1414,17 @@ mod tests {
 	fn one_step() {
 		let mut vm = create_vm();
 		let result = vm.step(&mut empty(), &mut sink());
-		assert_eq!(result, Ok(true), "Take one noop step.");
+		assert_eq!(
+			result,
+			Ok(StepInfo {
+				halted: false,
+				pointer_before: 0,
+				pointer_after: 1,
+				opcode: 21,
+				mnemonic: "noop",
+			}),
+			"Take one noop step."
+		);
 	}
 
 	#[test]
@@ -394,7 +1432,17 @@ mod tests {
 		let mut vm = create_vm();
 		vm.pointer = 3;
 		let result = vm.step(&mut empty(), &mut sink());
-		assert_eq!(result, Ok(false), "Take one halt step.");
+		assert_eq!(
+			result,
+			Ok(StepInfo {
+				halted: true,
+				pointer_before: 3,
+				pointer_after: 3,
+				opcode: 0,
+				mnemonic: "halt",
+			}),
+			"Take one halt step."
+		);
 	}
 
 	#[test]
@@ -404,11 +1452,36 @@ mod tests {
 		let result = vm.step(&mut empty(), &mut sink());
 		assert_eq!(
 			result,
-			Err("Out of range 4!".to_string()),
+			Err("Ran past end of memory at 4!".to_string()),
 			"Take invalid step."
 		);
 	}
 
+	#[test]
+	fn run_off_the_end() {
+		let running = AtomicBool::new(true);
+		// 0: noop, 1: add r0 r0 1
+		let mut vm = VM::new(Data::new(&[21, 9, 32768, 32768, 1]));
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Err("Ran past end of memory at 5; last instruction add@1".to_string())
+		);
+	}
+
+	#[test]
+	fn error_at_source_line() {
+		let parsing = crate::compiler::parse("noop\npop 32768\n".as_bytes()).unwrap();
+		let memory = [21, 3, 32768];
+		let mut vm = VM::new(Data::new(&memory))
+			.with_source_map(SourceMap::from_parsing(&parsing, "pop.asm"));
+		assert_eq!(
+			vm.run_for_n_steps(2, &mut empty(), &mut sink()),
+			Err(
+				"Error at pop.asm:2 (address 0x0001):\n\tStack was empty when popping!".to_string()
+			)
+		);
+	}
+
 	#[test]
 	fn run_to_completion() {
 		let mut vm = create_vm();
@@ -416,9 +1489,760 @@ mod tests {
 		let result = vm.run(&mut empty(), &mut output);
 		assert_eq!(
 			result,
-			Ok(()),
+			Ok(ExitReason::Halt),
 			"Run the small program to completion. (noop, out 77, halt)"
 		);
 		assert_eq!(String::from_utf8(output), Ok("M".to_string()));
+		assert_eq!(
+			create_vm().run(&mut empty(), &mut sink()),
+			Ok(ExitReason::Halt),
+			"Running again should reuse the Ctrl-C handler."
+		);
+	}
+
+	#[test]
+	fn run_interrupted() {
+		let mut vm = create_vm();
+		let running = AtomicBool::new(true);
+		running.store(false, Ordering::SeqCst);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::Interrupt),
+			"Stop when interrupted."
+		);
+		assert_eq!(vm.pointer, 0, "No step should be taken once interrupted.");
+	}
+
+	#[test]
+	fn run_to_output_limit() {
+		let running = AtomicBool::new(true);
+		// 0: out 65, jmp 0
+		const PROGRAM: &[u16] = &[19, 65, 6, 0];
+		let mut output = Vec::new();
+		let mut vm = VM::new(Data::new(PROGRAM)).with_max_output(3);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut output),
+			Err("Output limit of 3 bytes exceeded".to_string())
+		);
+		assert_eq!(output, b"AAA", "Stop writing at the limit.");
+
+		let mut output = Vec::new();
+		let mut vm = VM::new(Data::new(PROGRAM))
+			.with_max_output(3)
+			.with_buffered_output(2);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut output),
+			Err("Output limit of 3 bytes exceeded".to_string())
+		);
+		assert_eq!(output, b"AAA", "Buffered output should be cut short too.");
+	}
+
+	#[test]
+	fn count_steps() {
+		let running = AtomicBool::new(true);
+		// 0: set r0 3, add r0 r0 32767, jt r0 3, halt
+		const COUNTDOWN: &[u16] = &[1, 32768, 3, 9, 32768, 32768, 32767, 7, 32768, 3, 0];
+		let counter = Arc::new(AtomicU64::new(0));
+		let mut vm = VM::new(Data::new(COUNTDOWN)).with_step_counter(Arc::clone(&counter));
+		vm.enable_stats();
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::Halt)
+		);
+		assert_eq!(counter.load(Ordering::Relaxed), 8);
+		assert_eq!(
+			counter.load(Ordering::Relaxed),
+			vm.statistics().instructions_executed
+		);
+	}
+
+	#[test]
+	fn run_to_step_limit() {
+		let running = AtomicBool::new(true);
+		// 0: jmp 0
+		let mut vm = VM::new(Data::new(&[6, 0])).with_max_steps(100);
+		vm.enable_stats();
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::StepLimit(100))
+		);
+		assert_eq!(vm.statistics().instructions_executed, 100);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::StepLimit(100)),
+			"Each run should count from zero."
+		);
+		assert_eq!(vm.statistics().instructions_executed, 200);
+
+		let mut vm = create_vm().with_max_steps(100);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::Halt)
+		);
+	}
+
+	#[test]
+	fn run_debugger_script() {
+		// 0: set r0 5, add r1 r0 1, halt
+		const PROGRAM: &[u16] = &[1, 32768, 5, 9, 32769, 32768, 1, 0];
+		assert_eq!(
+			VM::run_script(PROGRAM, "step 2\nregs\n", empty(), sink()),
+			Ok("r0: 5\nr1: 6\nr2: 0\nr3: 0\nr4: 0\nr5: 0\nr6: 0\nr7: 0\n".to_string())
+		);
+		assert_eq!(
+			VM::run_script(
+				PROGRAM,
+				"# Skip the set\nbreak 3\ncontinue\nprint r0\ncontinue",
+				empty(),
+				sink()
+			),
+			Ok("Breakpoint at 3.\nr0: 5\nHalted.\n".to_string())
+		);
+		assert_eq!(
+			VM::run_script(PROGRAM, "step\nprint r9", empty(), sink()),
+			Err("Error on line 2 of the script.\n\t\"r9\" is not a register!".to_string())
+		);
+		assert_eq!(
+			VM::run_script(PROGRAM, "jump 2\npc", empty(), sink()),
+			Ok("Warning: 2 isn't the start of an instruction.\npc: 2\n".to_string())
+		);
+	}
+
+	#[test]
+	fn set_pointer_bounds() {
+		let mut vm = create_vm();
+		assert_eq!(
+			vm.set_pointer(2),
+			Ok(Some("2 isn't the start of an instruction.".to_string()))
+		);
+		assert_eq!(vm.pointer, 2, "Move even when warning.");
+		assert_eq!(vm.set_pointer(3), Ok(None));
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()).map(|info| info.halted),
+			Ok(true),
+			"Run the halt."
+		);
+		assert_eq!(
+			vm.set_pointer(4),
+			Err("Can't jump to 4, it's outside of memory!".to_string())
+		);
+		assert_eq!(vm.pointer, 3, "A failed jump shouldn't move the pointer.");
+	}
+
+	#[test]
+	fn hooks_in_order() {
+		// 0: set r0 1, add r0 r0 1, jt r0 10, out 65, halt
+		const PROGRAM: &[u16] = &[1, 32768, 1, 9, 32768, 32768, 1, 7, 32768, 10, 19, 65, 0];
+		let before = Rc::new(RefCell::new(Vec::new()));
+		let after = Rc::new(RefCell::new(Vec::new()));
+		let mut vm = VM::new(Data::new(PROGRAM));
+		let recorded = Rc::clone(&before);
+		vm.add_pre_hook(move |pointer, opcode| recorded.borrow_mut().push((pointer, opcode)));
+		let recorded = Rc::clone(&after);
+		vm.add_post_hook(move |_, opcode, data| {
+			recorded
+				.borrow_mut()
+				.push((opcode, data.get_register(0).unwrap()))
+		});
+		assert_eq!(
+			vm.step_n(5, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Halted)
+		);
+		assert_eq!(
+			*before.borrow(),
+			vec![(0, 1), (3, 9), (7, 7), (10, 19), (12, 0)],
+			"Every visited opcode should be seen before it runs."
+		);
+		assert_eq!(
+			*after.borrow(),
+			vec![(1, 1), (9, 2), (7, 2), (19, 2), (0, 2)],
+			"Post hooks should see the data after each instruction."
+		);
+	}
+
+	#[test]
+	fn step_back_through_history() {
+		// 0: set r0 5, push r0, wmem 20 r0, call 12, 12: pop r1
+		const PROGRAM: &[u16] = &[1, 32768, 5, 2, 32768, 16, 20, 32768, 17, 12, 0, 0, 3, 32769];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		vm.enable_history(3);
+		assert_eq!(vm.step_back(), Ok(false), "There's nothing to undo yet.");
+		assert_eq!(
+			vm.step_n(5, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Running)
+		);
+		assert_eq!(vm.data.registers()[1], 10, "The return address was popped.");
+
+		for _ in 0..3 {
+			assert_eq!(vm.step_back(), Ok(true));
+		}
+		assert_eq!(vm.pointer, 5, "Back before the wmem.");
+		assert_eq!(vm.data.stack(), &[5]);
+		assert_eq!(vm.data.fetch(20), Ok(0), "The wmem should be undone.");
+		assert!(vm.call_stack().is_empty());
+		assert_eq!(vm.step_back(), Ok(false), "Only three steps are kept.");
+
+		let mut vm = VM::new(Data::new(PROGRAM));
+		vm.enable_history(10);
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(vm.data.registers()[0], 5);
+		assert_eq!(vm.step_back(), Ok(true));
+		assert_eq!(vm.data.registers()[0], 0, "r0 should be restored.");
+		assert_eq!(vm.pointer, 0, "The pointer should be restored.");
+	}
+
+	#[test]
+	fn call_at_memory_end() {
+		let mut memory = vec![0; 32768];
+		// 0: jmp 32765, 32765: call 0
+		memory[..2].copy_from_slice(&[6, 32765]);
+		memory[32765..32767].copy_from_slice(&[17, 0]);
+		let mut vm = VM::new(Data::new(&memory));
+		assert_eq!(
+			vm.step_n(2, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Running)
+		);
+		assert_eq!(
+			vm.data.stack(),
+			&[32767],
+			"The last address is a valid return."
+		);
+
+		// 0: jmp 32766, 32766: call 0
+		memory[..2].copy_from_slice(&[6, 32766]);
+		memory[32766..].copy_from_slice(&[17, 0]);
+		let mut vm = VM::new(Data::new(&memory));
+		assert_eq!(
+			vm.step_n(2, &mut empty(), &mut sink()),
+			Err("Error at 32766:\n\tReturn address 32768 is outside of memory!".to_string())
+		);
+		assert!(vm.data.stack().is_empty(), "Nothing should be pushed.");
+	}
+
+	#[test]
+	fn step_three() {
+		const PROGRAM: &[u16] = &[21, 1, 32768, 5, 21, 9, 32768, 32768, 1, 0];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		assert_eq!(
+			vm.step_n(3, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Running)
+		);
+		assert_eq!(vm.pointer, 5, "Three steps should end before the add.");
+		assert_eq!(
+			vm.step_n(3, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Halted),
+			"Halt before taking all steps."
+		);
+
+		let mut vm = VM::new(Data::new(PROGRAM));
+		let id = vm.add_breakpoint(BreakpointKind::Address(4));
+		assert_eq!(
+			vm.step_n(3, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Breakpoint(4))
+		);
+		assert_eq!(vm.pointer, 4, "Stop at the breakpoint.");
+		assert!(vm.at_breakpoint());
+		assert_eq!(
+			vm.step_n(1, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Running)
+		);
+		assert_eq!(vm.pointer, 5, "Step off the breakpoint.");
+		assert_eq!(vm.list_breakpoints()[0].hits, 1);
+		vm.remove_breakpoint(id);
+		assert!(vm.list_breakpoints().is_empty());
+	}
+
+	#[test]
+	fn breakpoint_kinds() {
+		// 0: set r0 0, add r0 r0 1, wmem 20 r0, jmp 3
+		const PROGRAM: &[u16] = &[1, 32768, 0, 9, 32768, 32768, 1, 16, 20, 32768, 6, 3];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		vm.add_breakpoint(BreakpointKind::Opcode(16));
+		assert_eq!(
+			vm.step_n(10, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Breakpoint(7))
+		);
+		assert_eq!(vm.pointer, 7, "Stop before the wmem.");
+		assert_eq!(
+			vm.step_n(10, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Breakpoint(7))
+		);
+		assert_eq!(vm.pointer, 7, "Stop before the next wmem.");
+		assert_eq!(vm.list_breakpoints()[0].hits, 2);
+		vm.clear_breakpoints();
+
+		vm.add_breakpoint(BreakpointKind::RegisterEquals {
+			index: 0,
+			value: 4,
+		});
+		assert_eq!(
+			vm.step_n(20, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Breakpoint(7))
+		);
+		assert_eq!(vm.data.get_register(0), Ok(4));
+		assert_eq!(vm.pointer, 7, "Stop right after r0 became 4.");
+		vm.clear_breakpoints();
+
+		vm.add_breakpoint(BreakpointKind::MemoryEquals {
+			addr: 20,
+			value: 6,
+		});
+		assert_eq!(
+			vm.step_n(20, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Breakpoint(10))
+		);
+		assert_eq!(vm.data.get_register(0), Ok(6));
+		assert_eq!(vm.pointer, 10, "Stop right after memory 20 became 6.");
+		assert_eq!(
+			vm.step_n(20, &mut empty(), &mut sink()),
+			Ok(StepOutcome::Running)
+		);
+		assert_eq!(
+			vm.list_breakpoints()[0].hits,
+			1,
+			"Only stop when the memory starts to match."
+		);
+	}
+
+	#[test]
+	fn execution_stats() {
+		// set r0 3, wmem 100 r0, rmem r1 100, push r1, push r1, pop r2, halt
+		const PROGRAM: &[u16] = &[
+			1, 32768, 3, 16, 100, 32768, 15, 32769, 100, 2, 32769, 2, 32769, 3, 32770, 0,
+		];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		assert_eq!(vm.statistics(), ExecutionStats::default());
+		vm.enable_stats();
+		let running = AtomicBool::new(true);
+		assert_eq!(
+			vm.run_while(&running, &mut empty(), &mut sink()),
+			Ok(ExitReason::Halt)
+		);
+		let stats = vm.statistics();
+		assert_eq!(stats, ExecutionStats {
+			instructions_executed: 7,
+			memory_reads: 1,
+			memory_writes: 1,
+			stack_max_depth: 2,
+			elapsed: stats.elapsed,
+		});
+	}
+
+	#[test]
+	fn detect_infinite_loop() {
+		// 0: noop, jmp 0
+		const PROGRAM: &[u16] = &[21, 6, 0];
+		let mut vm = VM::new(Data::new(PROGRAM)).with_cycle_detection(true);
+		assert_eq!(
+			vm.run_for_n_steps(100_000, &mut empty(), &mut sink()),
+			Err("Error at 0:\n\tStuck in a loop repeating every 1000 steps!".to_string())
+		);
+
+		let mut vm = VM::new(Data::new(PROGRAM));
+		assert_eq!(
+			vm.run_for_n_steps(100_000, &mut empty(), &mut sink()),
+			Ok(true),
+			"Detection is off by default."
+		);
+
+		// set r0 20000, add r0 r0 32767, jt r0 3, halt
+		const COUNTDOWN: &[u16] = &[1, 32768, 20000, 9, 32768, 32768, 32767, 7, 32768, 3, 0];
+		let mut vm = VM::new(Data::new(COUNTDOWN)).with_cycle_detection(true);
+		assert_eq!(
+			vm.run_for_n_steps(100_000, &mut empty(), &mut sink()),
+			Ok(false),
+			"A long loop that ends isn't infinite."
+		);
+	}
+
+	#[test]
+	fn trace_branches() {
+		// 0: set r0 1, jt r0 7, halt, jf r0 2, halt
+		const PROGRAM: &[u16] = &[1, 32768, 1, 7, 32768, 7, 0, 8, 32768, 2, 0];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		vm.enable_branch_trace();
+		while !vm.step(&mut empty(), &mut sink()).unwrap().halted {}
+		assert_eq!(vm.branch_trace(), &[
+			BranchRecord {
+				addr: 3,
+				taken: true,
+			},
+			BranchRecord {
+				addr: 7,
+				taken: false,
+			},
+		]);
+		vm.clear_branch_trace();
+		assert!(vm.branch_trace().is_empty());
+	}
+
+	#[test]
+	fn coverage_of_one_arm() {
+		// 0: jf 0 6, out 65, halt, out 66, halt
+		const PROGRAM: &[u16] = &[8, 0, 6, 19, 65, 0, 19, 66, 0];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		assert_eq!(vm.coverage(), None);
+		vm.enable_coverage();
+		while !vm.step(&mut empty(), &mut sink()).unwrap().halted {}
+		let coverage = vm.coverage().unwrap();
+		assert!(
+			coverage.contains(&6) && coverage.contains(&8),
+			"The jump should be followed."
+		);
+		assert!(
+			!coverage.contains(&3),
+			"The arm not taken shouldn't be covered."
+		);
+		assert_eq!(coverage.len(), 3);
+	}
+
+	#[test]
+	fn register_write_callback() {
+		// set r0 42, set r1 7, set r0 43, halt
+		const PROGRAM: &[u16] = &[1, 32768, 42, 1, 32769, 7, 1, 32768, 43, 0];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		let writes = Rc::new(RefCell::new(Vec::new()));
+		let log = Rc::clone(&writes);
+		vm.data
+			.on_register_write(0, move |index, value| log.borrow_mut().push((index, value)))
+			.unwrap();
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(*writes.borrow(), vec![(0, 42)], "set r0 should call back.");
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(writes.borrow().len(), 1, "Only r0 has a callback.");
+		vm.data.clear_register_callbacks();
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(writes.borrow().len(), 1, "Cleared callbacks aren't called.");
+		assert_eq!(
+			vm.data.on_register_write(8, |_, _| {}),
+			Err(RuntimeError::RegisterOutOfRange(8))
+		);
+	}
+
+	#[test]
+	fn step_out_of_call() {
+		// 0: call 5, halt, noop, noop
+		// 5: noop, call 10, ret, halt
+		// 10: noop, ret
+		const PROGRAM: &[u16] = &[17, 5, 0, 21, 21, 21, 17, 10, 18, 0, 21, 18];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(vm.call_stack(), &[2]);
+		assert_eq!(vm.step_out(&mut empty(), &mut sink()), Ok(true));
+		assert_eq!(vm.pointer, 2, "Land on the return address.");
+		assert!(vm.call_stack().is_empty());
+	}
+
+	#[test]
+	fn step_over_call() {
+		// 0: call 5, out 77, halt
+		// 5: out 65, ret
+		const PROGRAM: &[u16] = &[17, 5, 19, 77, 0, 19, 65, 18];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		let mut output = Vec::new();
+		assert_eq!(vm.step_over(&mut empty(), &mut output), Ok(true));
+		assert_eq!(vm.pointer, 2, "Stop after the call.");
+		assert_eq!(output, b"A", "The callee should have run.");
+		assert_eq!(vm.step_over(&mut empty(), &mut output), Ok(true));
+		assert_eq!(vm.pointer, 4, "Other instructions are single steps.");
+	}
+
+	#[test]
+	fn in_line_endings() {
+		let program = [20, 32768].repeat(16);
+		let cases = [
+			(
+				LineEnding::CrLf,
+				&b"hello\r\nworld\r\n"[..],
+				&b"hello\nworld\n"[..],
+			),
+			(LineEnding::Lf, b"hello\nwor\rld\n", b"hello\nwor\rld\n"),
+			(LineEnding::Cr, b"hello\rworld\r", b"hello\nworld\n"),
+		];
+		for &(line_ending, input, expected) in cases.iter() {
+			let mut vm = VM::new(Data::new(&program)).with_line_ending(line_ending);
+			let mut input = input;
+			let mut read = Vec::new();
+			while !vm.step(&mut input, &mut sink()).unwrap().halted {
+				read.push(vm.data.get_register(0).unwrap() as u8);
+			}
+			assert_eq!(read, expected, "Reading {:?} line endings.", line_ending);
+		}
+	}
+
+	#[test]
+	fn read_injected_input() {
+		let mut program = [20, 32768, 19, 32768].repeat(7);
+		program.push(0);
+		let (mut vm, injector) = VM::new(Data::new(&program)).with_injector();
+		injector.send(b"hello\n");
+		let mut output = Vec::new();
+		while !vm.step(&mut &b"!"[..], &mut output).unwrap().halted {}
+		assert_eq!(
+			output, b"hello\n!",
+			"The injected bytes should be read before the input."
+		);
+	}
+
+	#[test]
+	fn newline_modes() {
+		let mut program = [20, 32768].repeat(3);
+		program.push(0);
+		let mut vm = VM::new(Data::new(&program)).with_newline_mode(NewlineMode::NormalizeLf);
+		let mut input = &b"a\r\nb"[..];
+		let mut read = Vec::new();
+		while !vm.step(&mut input, &mut sink()).unwrap().halted {
+			read.push(vm.data.get_register(0).unwrap() as u8);
+		}
+		assert_eq!(read, b"a\nb", "The CR before LF should be dropped.");
+
+		let mut vm = VM::new(Data::new(&program))
+			.with_line_ending(LineEnding::Cr)
+			.with_newline_mode(NewlineMode::Raw);
+		let mut input = &b"a\rb"[..];
+		let mut read = Vec::new();
+		while !vm.step(&mut input, &mut sink()).unwrap().halted {
+			read.push(vm.data.get_register(0).unwrap() as u8);
+		}
+		assert_eq!(read, b"a\rb", "Raw should pass the CR on as is.");
+
+		// 0: out 10, out 65, halt
+		const PROGRAM: &[u16] = &[19, 10, 19, 65, 0];
+		let mut output = Vec::new();
+		let mut vm = VM::new(Data::new(PROGRAM)).with_newline_mode(NewlineMode::TranslateLfToCrLf);
+		while !vm.step(&mut empty(), &mut output).unwrap().halted {}
+		assert_eq!(output, b"\r\nA");
+	}
+
+	#[test]
+	fn numeric_output() {
+		// 0: out 72, out 105, halt
+		const PROGRAM: &[u16] = &[19, 72, 19, 105, 0];
+		for (format, expected) in [(OutFormat::Dec, "72 105"), (OutFormat::Hex, "48 69")] {
+			let mut output = Vec::new();
+			let mut vm = VM::new(Data::new(PROGRAM)).with_out_format(format);
+			while !vm.step(&mut empty(), &mut output).unwrap().halted {}
+			assert_eq!(
+				String::from_utf8(output).unwrap(),
+				expected,
+				"Writing {:?}.",
+				format
+			);
+		}
+	}
+
+	#[test]
+	fn save_and_load() {
+		let mut vm = create_vm();
+		vm.pointer = 3;
+		vm.data.set_register(0, 1).unwrap();
+		vm.data.set_register(7, 32767).unwrap();
+		vm.data.write_memory(2, 65).unwrap();
+		vm.data.write_memory(1, 21).unwrap();
+		vm.data.push_stack(5);
+		vm.data.push_stack(6);
+
+		let mut save = Vec::new();
+		vm.save_to_writer(&mut save).unwrap();
+		#[rustfmt::skip]
+		let expected = vec![
+			0x59, 0x53, SAVE_FORMAT_VERSION,
+			3, 0,
+			1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 127,
+			2, 0, 0, 0, 1, 0, 21, 0, 2, 0, 65, 0,
+			2, 0, 0, 0, 5, 0, 6, 0,
+		];
+		assert_eq!(
+			save, expected,
+			"The save should follow the documented layout."
+		);
+
+		let loaded = VM::load_from_reader(MEMORY, &mut save.as_slice()).unwrap();
+		assert_eq!(loaded.pointer, 3);
+		assert_eq!(loaded.data.registers, vm.data.registers);
+		assert_eq!(loaded.data.memory_changes, vm.data.memory_changes);
+		assert_eq!(loaded.data.stack, vec![5, 6]);
+		assert_eq!(VM::load(MEMORY, &save).map(|v| v.pointer), Ok(3));
+	}
+
+	#[test]
+	fn load_invalid() {
+		assert_eq!(
+			VM::load_from_reader(MEMORY, &mut &[0x59, 0x54, 1][..]).map(|_| ()),
+			Err(RuntimeError::InvalidSave(
+				"It starts with 0x5459 instead of 0x5359.".to_string()
+			))
+		);
+		assert_eq!(
+			VM::load_from_reader(MEMORY, &mut &[0x59, 0x53, 1, 0][..]).map(|_| ()),
+			Err(RuntimeError::InvalidSave("It ended too early.".to_string()))
+		);
+	}
+
+	#[test]
+	fn load_save_versions() {
+		let mut save = Vec::new();
+		VM::new(Data::new(MEMORY))
+			.save_to_writer(&mut save)
+			.unwrap();
+		assert_eq!(save[2], SAVE_FORMAT_VERSION);
+		assert!(
+			VM::load_from_reader(MEMORY, &mut save.as_slice()).is_ok(),
+			"The current version should load."
+		);
+
+		save[2] = 255;
+		assert_eq!(
+			VM::load_from_reader(MEMORY, &mut save.as_slice()).map(|_| ()),
+			Err(RuntimeError::IncompatibleSaveVersion {
+				expected: SAVE_FORMAT_VERSION,
+				found: 255,
+			})
+		);
+		assert_eq!(
+			VM::load(MEMORY, &save).map(|_| ()),
+			Err(
+				"The save file is from format version 255, but only version 1 can be loaded. Was \
+				 it made by a different build?"
+					.to_string()
+			)
+		);
+	}
+
+	/// Records the output written before each flush.
+	#[derive(Default)]
+	struct FlushTracker {
+		buffer: Vec<u8>,
+		flushed: Vec<String>,
+	}
+
+	impl Write for FlushTracker {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.buffer.write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			self.flushed
+				.push(String::from_utf8(self.buffer.split_off(0)).unwrap());
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn flush_after_newline() {
+		const PROGRAM: &[u16] = &[19, 72, 19, 105, 19, 10, 19, 33, 19, 10, 19, 63, 0];
+		let mut vm = VM::new(Data::new(PROGRAM));
+		let mut output = FlushTracker::default();
+		while !vm.step(&mut empty(), &mut output).unwrap().halted {}
+		assert_eq!(
+			output.flushed,
+			vec!["Hi\n", "!\n"],
+			"Output should be flushed at each newline."
+		);
+		assert_eq!(output.buffer, b"?", "The last line isn't flushed yet.");
+	}
+
+	#[test]
+	fn buffered_output() {
+		// out 72, out 105, out 10, in r0, out r0, halt
+		const PROGRAM: &[u16] = &[19, 72, 19, 105, 19, 10, 20, 32768, 19, 32768, 0];
+		let mut vm = VM::new(Data::new(PROGRAM)).with_buffered_output(64);
+		let mut output = FlushTracker::default();
+		vm.run_for_n_steps(3, &mut empty(), &mut output).unwrap();
+		assert!(output.buffer.is_empty(), "Output should be held back.");
+		vm.step(&mut &b"!"[..], &mut output).unwrap();
+		assert_eq!(
+			output.flushed,
+			vec!["Hi\n"],
+			"Output should be written before input."
+		);
+		assert_eq!(
+			vm.run_while(&AtomicBool::new(true), &mut empty(), &mut output),
+			Ok(ExitReason::Halt)
+		);
+		assert_eq!(
+			output.flushed,
+			vec!["Hi\n", "!"],
+			"Output should be written at halt."
+		);
+
+		let mut vm = VM::new(Data::new(PROGRAM)).with_buffered_output(2);
+		let mut output = Vec::new();
+		vm.run_for_n_steps(3, &mut empty(), &mut output).unwrap();
+		assert_eq!(output, b"Hi", "A full buffer should be written.");
+		vm.flush_output(&mut output).unwrap();
+		assert_eq!(output, b"Hi\n");
+	}
+
+	#[test]
+	fn rmem_access_log() {
+		let mut vm = VM::new(Data::new(&[21, 15, 32768, 5, 0, 42]));
+		vm.data.enable_access_log();
+		while !vm.step(&mut empty(), &mut sink()).unwrap().halted {}
+		assert_eq!(
+			vm.data.access_log(),
+			&[MemoryAccess {
+				addr: 5,
+				kind: AccessKind::Read,
+				value: 42,
+				instruction_pointer: 1,
+			}],
+			"rmem should log a read of its address."
+		);
+	}
+
+	#[test]
+	fn eof_policies() {
+		const IN: &[u16] = &[20, 32768, 0];
+
+		let mut vm = VM::new(Data::new(IN));
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()).map(|info| info.halted),
+			Ok(true),
+			"Halt at EOF."
+		);
+
+		let mut vm = VM::new(Data::new(IN)).with_eof_policy(EofPolicy::Error);
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()),
+			Err("Error at 0:\n\tReached end of input!".to_string()),
+			"Error at EOF."
+		);
+
+		let mut vm = VM::new(Data::new(IN)).with_eof_policy(EofPolicy::Block);
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()),
+			Err("Error at 0:\n\tReached end of input, with no injector to wait on!".to_string()),
+			"Nothing to wait for at EOF."
+		);
+
+		let (mut vm, injector) = VM::new(Data::new(IN))
+			.with_eof_policy(EofPolicy::Block)
+			.with_injector();
+		let sender = thread::spawn(move || injector.send(b"a"));
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()).map(|info| info.halted),
+			Ok(false),
+			"Wait at EOF."
+		);
+		sender.join().unwrap();
+		assert_eq!(vm.pointer, 0, "Retry the same instruction after waiting.");
+		vm.step(&mut empty(), &mut sink()).unwrap();
+		assert_eq!(
+			vm.data.get_register(0),
+			Ok(b'a' as u16),
+			"Read what was sent."
+		);
+
+		let (mut vm, injector) = VM::new(Data::new(IN))
+			.with_eof_policy(EofPolicy::Block)
+			.with_injector();
+		let closer = thread::spawn(move || injector.close());
+		assert_eq!(
+			vm.step(&mut empty(), &mut sink()).map(|info| info.halted),
+			Ok(true),
+			"Stop once nothing more can be sent."
+		);
+		closer.join().unwrap();
 	}
 }
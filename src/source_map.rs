@@ -0,0 +1,226 @@
+use std::{
+	collections::BTreeMap,
+	convert::TryFrom,
+	fmt,
+	io::{Read, Write},
+	iter::Peekable,
+	str::Chars,
+};
+
+use crate::{compiler::Parsing, text::skip_whitespace};
+
+/// Where an instruction came from in the assembly source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+	pub file: String,
+	pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}", self.file, self.line)
+	}
+}
+
+/// The source location of every compiled instruction, stored as a JSON array
+/// of `{ "addr": N, "file": "source.asm", "line": N }` objects.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+	locations: BTreeMap<u16, SourceLocation>,
+}
+
+impl SourceMap {
+	/// Maps every instruction of `parsing` to its line in `file`.
+	pub fn from_parsing(parsing: &Parsing, file: &str) -> Self {
+		let locations = parsing
+			.source_lines()
+			.into_iter()
+			.map(|(addr, line)| {
+				(addr, SourceLocation {
+					file: file.to_string(),
+					line,
+				})
+			})
+			.collect();
+		Self {
+			locations,
+		}
+	}
+
+	/// The location of the instruction starting at `addr`.
+	pub fn location(&self, addr: usize) -> Option<&SourceLocation> {
+		u16::try_from(addr)
+			.ok()
+			.and_then(|addr| self.locations.get(&addr))
+	}
+
+	/// Writes the map as JSON, one location per line in address order.
+	pub fn write<O: Write>(&self, out: &mut O) -> Result<(), String> {
+		let entries = self
+			.locations
+			.iter()
+			.map(|(addr, location)| {
+				format!(
+					"\t{{ \"addr\": {}, \"file\": \"{}\", \"line\": {} }}",
+					addr,
+					escape(&location.file),
+					location.line
+				)
+			})
+			.collect::<Vec<_>>();
+		writeln!(out, "[\n{}\n]", entries.join(",\n"))
+			.and_then(|_| out.flush())
+			.map_err(|e| format!("Could not write to the source map file. {}", e))
+	}
+
+	pub fn read<I: Read>(mut input: I) -> Result<Self, String> {
+		let mut json = String::new();
+		input
+			.read_to_string(&mut json)
+			.map_err(|e| format!("Error reading source map. {}", e))?;
+		let mut chars = json.chars().peekable();
+		let mut locations = BTreeMap::new();
+		expect(&mut chars, '[')?;
+		if skip_whitespace(&mut chars) == Some(']') {
+			chars.next();
+		} else {
+			loop {
+				let (addr, location) = read_entry(&mut chars)?;
+				locations.insert(addr, location);
+				match skip_whitespace(&mut chars) {
+					Some(',') => {
+						chars.next();
+					}
+					_ => break,
+				}
+			}
+			expect(&mut chars, ']')?;
+		}
+		Ok(Self {
+			locations,
+		})
+	}
+}
+
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+	match skip_whitespace(chars) {
+		Some(c) if c == expected => {
+			chars.next();
+			Ok(())
+		}
+		Some(c) => Err(format!(
+			"Malformed source map! Expected '{}' but found '{}'.",
+			expected, c
+		)),
+		None => Err(format!(
+			"Malformed source map! Expected '{}' but it ended.",
+			expected
+		)),
+	}
+}
+
+fn read_entry(chars: &mut Peekable<Chars>) -> Result<(u16, SourceLocation), String> {
+	let (mut addr, mut file, mut line) = (None, None, None);
+	expect(chars, '{')?;
+	loop {
+		let key = read_string(chars)?;
+		expect(chars, ':')?;
+		match key.as_str() {
+			"addr" => addr = Some(read_number(chars)?),
+			"file" => file = Some(read_string(chars)?),
+			"line" => line = Some(read_number(chars)?),
+			_ => return Err(format!("Malformed source map! Unknown key \"{}\".", key)),
+		}
+		if skip_whitespace(chars) != Some(',') {
+			break;
+		}
+		chars.next();
+	}
+	expect(chars, '}')?;
+	match (addr.map(u16::try_from), file, line) {
+		(Some(Ok(addr)), Some(file), Some(line)) => Ok((addr, SourceLocation {
+			file,
+			line,
+		})),
+		_ => Err("Malformed source map! Every entry needs an addr, file and line.".to_string()),
+	}
+}
+
+fn read_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+	expect(chars, '"')?;
+	let mut s = String::new();
+	loop {
+		match chars.next() {
+			Some('"') => return Ok(s),
+			Some('\\') => match chars.next() {
+				Some(c @ ('"' | '\\' | '/')) => s.push(c),
+				Some('n') => s.push('\n'),
+				Some('t') => s.push('\t'),
+				_ => return Err("Malformed source map! Unsupported escape.".to_string()),
+			},
+			Some(c) => s.push(c),
+			None => return Err("Malformed source map! A string never ends.".to_string()),
+		}
+	}
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> Result<usize, String> {
+	skip_whitespace(chars);
+	let mut digits = String::new();
+	while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+		digits.push(c);
+		chars.next();
+	}
+	digits
+		.parse()
+		.map_err(|_| "Malformed source map! Expected a number.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::parse;
+
+	#[test]
+	fn write_and_read() {
+		let parsing = parse("start: out 65\n\n# Stop\nhalt\n".as_bytes()).unwrap();
+		let map = SourceMap::from_parsing(&parsing, "dir\\\"a\".asm");
+		let mut out = Vec::new();
+		map.write(&mut out).unwrap();
+		let expected = [
+			"[",
+			"\t{ \"addr\": 0, \"file\": \"dir\\\\\\\"a\\\".asm\", \"line\": 1 },",
+			"\t{ \"addr\": 2, \"file\": \"dir\\\\\\\"a\\\".asm\", \"line\": 4 }",
+			"]",
+			"",
+		];
+		assert_eq!(String::from_utf8(out.clone()), Ok(expected.join("\n")));
+		assert_eq!(
+			SourceMap::read(out.as_slice()),
+			Ok(map.clone()),
+			"Reading a written map should give back the same locations."
+		);
+		assert_eq!(
+			map.location(2).map(ToString::to_string),
+			Some("dir\\\"a\".asm:4".to_string())
+		);
+		assert_eq!(map.location(1), None);
+	}
+
+	#[test]
+	fn read_malformed() {
+		assert_eq!(SourceMap::read("[]".as_bytes()), Ok(SourceMap::default()));
+		assert_eq!(
+			SourceMap::read("[{\"addr\": 1, \"line\": 2}]".as_bytes()),
+			Err("Malformed source map! Every entry needs an addr, file and line.".to_string())
+		);
+		assert_eq!(
+			SourceMap::read("[{\"addr\": 1, \"file\": \"a\", \"line\": 2}".as_bytes()),
+			Err("Malformed source map! Expected ']' but it ended.".to_string())
+		);
+	}
+}
@@ -0,0 +1,9 @@
+use std::{iter::Peekable, str::Chars};
+
+/// Skips whitespace, returning the character after it without consuming it.
+pub(crate) fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+	while chars.peek().is_some_and(|c| c.is_whitespace()) {
+		chars.next();
+	}
+	chars.peek().copied()
+}